@@ -1,4 +1,5 @@
-use clap::{ArgGroup, Args, Parser, Subcommand};
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use std::ffi::OsString;
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -8,6 +9,15 @@ use std::path::PathBuf;
     about = "Store encrypted environment variables in .env files"
 )]
 pub struct Cli {
+    #[arg(
+        long,
+        short = 'c',
+        global = true,
+        value_name = "PATH",
+        help = "Path to project config [default: .sealed.toml if present]"
+    )]
+    pub config: Option<PathBuf>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -20,11 +30,128 @@ pub enum Commands {
     Get(GetArgs),
     #[command(about = "Generate a new random key (base64)")]
     Keygen(KeygenArgs),
+    #[command(about = "Run a command with the decrypted env file in its environment")]
+    Run(RunArgs),
+    #[command(about = "List variables in an env file and whether they are encrypted")]
+    List(ListArgs),
+    #[command(about = "Remove a variable from an env file", visible_alias = "rm")]
+    Unset(UnsetArgs),
+    #[command(about = "Re-encrypt every value in an env file under a new key")]
+    Rekey(RekeyArgs),
+    #[command(about = "Manage the recipients that values are encrypted to")]
+    Recipients(RecipientsArgs),
+    #[command(about = "Check that every value in an env file is well-formed and decrypts")]
+    Verify(VerifyArgs),
+    #[command(about = "Check an env file against the project's encryption policy")]
+    Check(CheckArgs),
+    #[command(about = "Find plaintext values that look like credentials")]
+    Scan(ScanArgs),
+    #[command(about = "Write a decrypted copy of an env file")]
+    Unseal(UnsealArgs),
+    #[command(
+        about = "Encrypt every plaintext value in an env file in place",
+        visible_alias = "encrypt-file"
+    )]
+    Seal(SealArgs),
+    #[command(about = "Remove duplicate assignments from an env file, keeping the effective value")]
+    Dedupe(DedupeArgs),
+}
+
+#[derive(Args)]
+pub struct KeyArgs {
+    #[arg(
+        long,
+        short = 'k',
+        value_name = "BASE64",
+        help = "Read key from base64-encoded argument (repeat to try several keys when decrypting)"
+    )]
+    pub key: Vec<String>,
+
+    #[arg(
+        long = "key-file",
+        short = 'K',
+        value_name = "PATH",
+        help = "Read key from a file (base64)"
+    )]
+    pub key_file: Option<PathBuf>,
+
+    #[arg(
+        long = "keyring-file",
+        value_name = "PATH",
+        help = "Read several keys from a file (base64, one per line) to try when decrypting"
+    )]
+    pub keyring_file: Option<PathBuf>,
+
+    #[arg(long = "key-stdin", short = 'S', help = "Read key from stdin (base64)")]
+    pub key_stdin: bool,
+
+    #[arg(
+        long = "key-fd",
+        value_name = "FD",
+        help = "Read key from an inherited file descriptor (base64)"
+    )]
+    pub key_fd: Option<u32>,
+
+    #[arg(
+        long = "key-command",
+        value_name = "COMMAND",
+        help = "Read key from the stdout of a shell command (base64)"
+    )]
+    pub key_command: Option<String>,
+
+    #[arg(
+        long = "passphrase-file",
+        value_name = "PATH",
+        help = "Derive key from a passphrase read from a file"
+    )]
+    pub passphrase_file: Option<PathBuf>,
+
+    #[arg(
+        long = "passphrase-stdin",
+        help = "Derive key from a passphrase read from stdin"
+    )]
+    pub passphrase_stdin: bool,
+
+    #[arg(
+        long,
+        short = 'I',
+        value_name = "PATH",
+        help = "Read an X25519 identity for recipient-encrypted values from a file"
+    )]
+    pub identity: Option<PathBuf>,
+}
+
+impl KeyArgs {
+    pub fn uses_stdin(&self) -> bool {
+        self.key_stdin || self.passphrase_stdin
+    }
+}
+
+#[derive(Args)]
+pub struct NewKeyArgs {
+    #[arg(
+        long = "new-key",
+        short = 'n',
+        value_name = "BASE64",
+        help = "Read new key from base64-encoded argument"
+    )]
+    pub new_key: Option<String>,
+
+    #[arg(
+        long = "new-key-file",
+        short = 'N',
+        value_name = "PATH",
+        help = "Read new key from a file (base64)"
+    )]
+    pub new_key_file: Option<PathBuf>,
+
+    #[arg(long = "new-key-stdin", help = "Read new key from stdin (base64)")]
+    pub new_key_stdin: bool,
 }
 
 #[derive(Args)]
 #[command(
-    long_about = "Encrypt a plaintext value and store it as ENCv1:<nonce>:<ciphertext> in the env file.\nValue input: exactly one of --stdin, --value (with --allow-argv), or --value-file.\nKey input: exactly one of --key, --key-file, --key-stdin, or SEALED_KEY (env var)."
+    long_about = "Encrypt a plaintext value and store it as ENCv2:<algorithm>:<key id>:<nonce>:<ciphertext> in the env file.\nIf the project config lists recipients, the value is instead encrypted to them (ENCr1) and no key is needed.\nWith a context (--context, or the env file's entry in [contexts] of the project config) the value is bound to it (ENCv3/ENCr2) and only decrypts in the same context.\nValue input: exactly one of --stdin, --value (with --allow-argv), or --value-file.\nKey input: exactly one of --key, --key-file, --key-stdin, --key-fd, --key-command, --passphrase-file, --passphrase-stdin, SEALED_KEY, or SEALED_PASSPHRASE (env vars); otherwise key_command from the project config."
 )]
 #[command(
     group(
@@ -67,25 +194,95 @@ pub struct SetArgs {
     )]
     pub allow_argv: bool,
 
+    #[command(flatten)]
+    pub key_args: KeyArgs,
+
+    #[arg(
+        long = "env-file",
+        short = 'e',
+        value_name = "PATH",
+        default_value = ".env",
+        help = "Path to env file"
+    )]
+    pub env_file: PathBuf,
+
     #[arg(
         long,
-        short = 'k',
-        value_name = "BASE64",
-        help = "Read key from base64-encoded argument"
+        value_name = "NAME",
+        help = "Context (environment name) the values are bound to [default: from [contexts] in the project config]"
     )]
-    pub key: Option<String>,
+    pub context: Option<String>,
+}
 
+#[derive(Args)]
+#[command(
+    long_about = "Read a variable from the env file. If the value is encrypted, a key is required to decrypt it (from --key/--key-file/--key-stdin/--key-fd/--key-command, a passphrase, SEALED_KEY, or key_command in the project config; several keys can be given with repeated --key, --keyring-file, or SEALED_KEYS and are picked by key id or tried in turn; recipient-encrypted values need --identity or SEALED_IDENTITY).\nValues bound to a context (ENCv3/ENCr2) only decrypt in that context: --context, or the env file's entry in [contexts] of the project config.\nWithout --reveal, plaintext is not printed."
+)]
+pub struct GetArgs {
     #[arg(
-        long = "key-file",
-        short = 'K',
+        value_name = "VAR_NAME",
+        help = "Environment variable name (used as AAD)"
+    )]
+    pub var_name: String,
+
+    #[arg(
+        long = "env-file",
+        short = 'e',
         value_name = "PATH",
-        help = "Read key from a file (base64)"
+        default_value = ".env",
+        help = "Path to env file"
     )]
-    pub key_file: Option<PathBuf>,
+    pub env_file: PathBuf,
 
-    #[arg(long = "key-stdin", short = 'S', help = "Read key from stdin (base64)")]
-    pub key_stdin: bool,
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Context (environment name) the values are bound to [default: from [contexts] in the project config]"
+    )]
+    pub context: Option<String>,
+
+    #[arg(long, short = 'r', help = "Print decrypted plaintext to stdout")]
+    pub reveal: bool,
+
+    #[command(flatten)]
+    pub key_args: KeyArgs,
+}
+
+#[derive(Args)]
+#[command(
+    long_about = "Generate a new random 32-byte key and print it as base64. Its key id, used for key:<id> recipients, is printed on stderr.\nWith --passphrase, instead write a random Argon2id salt to the [passphrase] table of the project config; the key is then derived from a shared passphrase (--passphrase-file, --passphrase-stdin, or SEALED_PASSPHRASE).\nWith --recipient, generate an X25519 identity (private, keep it secret) and print its public recipient on stderr; add the recipient to `recipients` in the project config so `set` can encrypt without the identity."
+)]
+pub struct KeygenArgs {
+    #[arg(
+        long = "out-file",
+        short = 'o',
+        value_name = "PATH",
+        help = "Write base64 key to a file instead of stdout"
+    )]
+    pub out_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        short = 'p',
+        conflicts_with = "out_file",
+        help = "Set up passphrase-derived keys in the project config"
+    )]
+    pub passphrase: bool,
+
+    #[arg(
+        long,
+        short = 'r',
+        conflicts_with = "passphrase",
+        help = "Generate an X25519 identity and print its public recipient"
+    )]
+    pub recipient: bool,
+}
 
+#[derive(Args)]
+#[command(
+    long_about = "Decrypt every encrypted value in the env file and run COMMAND with the variables merged over the current environment.\nA key is required only if the env file contains encrypted values (from --key/--key-file/--key-stdin/--key-fd/--key-command, a passphrase, SEALED_KEY, or key_command in the project config; several keys can be given with repeated --key, --keyring-file, or SEALED_KEYS and are picked by key id or tried in turn; recipient-encrypted values need --identity or SEALED_IDENTITY).\nValues bound to a context (ENCv3/ENCr2) only decrypt in that context: --context, or the env file's entry in [contexts] of the project config.\nThe exit code of COMMAND is propagated."
+)]
+pub struct RunArgs {
     #[arg(
         long = "env-file",
         short = 'e',
@@ -94,17 +291,73 @@ pub struct SetArgs {
         help = "Path to env file"
     )]
     pub env_file: PathBuf,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Context (environment name) the values are bound to [default: from [contexts] in the project config]"
+    )]
+    pub context: Option<String>,
+
+    #[command(flatten)]
+    pub key_args: KeyArgs,
+
+    #[arg(
+        value_name = "COMMAND",
+        required = true,
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        help = "Command to run, followed by its arguments"
+    )]
+    pub command: Vec<OsString>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
 }
 
 #[derive(Args)]
 #[command(
-    long_about = "Read a variable from the env file. If the value is encrypted, a key is required to decrypt it (from --key/--key-file/--key-stdin or SEALED_KEY).\nWithout --reveal, plaintext is not printed."
+    long_about = "List every variable in the env file with its line number, whether the value is encrypted, and for ENCv2 values the id of the key that encrypted it.\nIf a key is provided (from --key/--key-file/--key-stdin/--key-fd/--key-command, a passphrase, SEALED_KEY, or key_command in the project config; several keys can be given with repeated --key, --keyring-file, or SEALED_KEYS and are picked by key id or tried in turn; recipient-encrypted values need --identity or SEALED_IDENTITY), also report whether each encrypted value decrypts. Values are never printed."
 )]
-pub struct GetArgs {
+pub struct ListArgs {
     #[arg(
-        value_name = "VAR_NAME",
-        help = "Environment variable name (used as AAD)"
+        long = "env-file",
+        short = 'e',
+        value_name = "PATH",
+        default_value = ".env",
+        help = "Path to env file"
     )]
+    pub env_file: PathBuf,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Context (environment name) the values are bound to [default: from [contexts] in the project config]"
+    )]
+    pub context: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value = "text",
+        help = "Output format"
+    )]
+    pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub key_args: KeyArgs,
+}
+
+#[derive(Args)]
+#[command(
+    long_about = "Remove every occurrence of a variable (including export-prefixed lines) from the env file.\nComments, ordering, whitespace and line endings of the remaining lines are preserved.\nIf the env file has a sealed-mac trailer (or the project config sets mac = true), a key is required to verify and update it."
+)]
+pub struct UnsetArgs {
+    #[arg(value_name = "VAR_NAME", help = "Environment variable name")]
     pub var_name: String,
 
     #[arg(
@@ -116,36 +369,338 @@ pub struct GetArgs {
     )]
     pub env_file: PathBuf,
 
-    #[arg(long, short = 'r', help = "Print decrypted plaintext to stdout")]
-    pub reveal: bool,
+    #[arg(
+        long = "ignore-missing",
+        short = 'i',
+        help = "Succeed even if the variable is not present"
+    )]
+    pub ignore_missing: bool,
+
+    #[command(flatten)]
+    pub key_args: KeyArgs,
+}
+
+#[derive(Args)]
+#[command(
+    long_about = "Decrypt every ENCv2/ENCv1 value in the env file with the current key(s) and re-encrypt it with the new key using fresh nonces. Values already encrypted with the new key (in the env file's context) and values encrypted to recipients are left unchanged. Values not yet bound to a context are bound to the env file's context, if one is configured.\nCurrent key: exactly one of --key (repeatable), --key-file, --keyring-file, --key-stdin, --key-fd, --key-command, --passphrase-file, --passphrase-stdin, SEALED_KEY and/or SEALED_KEYS, or SEALED_PASSPHRASE (env vars); otherwise key_command from the project config.\nNew key: exactly one of --new-key, --new-key-file, or --new-key-stdin.\nThe env file is replaced atomically and left untouched if any value fails to decrypt."
+)]
+pub struct RekeyArgs {
+    #[arg(
+        long = "env-file",
+        short = 'e',
+        value_name = "PATH",
+        default_value = ".env",
+        help = "Path to env file"
+    )]
+    pub env_file: PathBuf,
 
     #[arg(
         long,
-        short = 'k',
-        value_name = "BASE64",
-        help = "Read key from base64-encoded argument"
+        value_name = "NAME",
+        help = "Context (environment name) the values are bound to [default: from [contexts] in the project config]"
+    )]
+    pub context: Option<String>,
+
+    #[command(flatten)]
+    pub key_args: KeyArgs,
+
+    #[command(flatten)]
+    pub new_key_args: NewKeyArgs,
+}
+
+#[derive(Args)]
+#[command(
+    long_about = "Manage the recipients listed in the project config. Each value's data key is wrapped once per recipient, so a team member can be added or revoked without sharing a single key.\nRecipients are X25519 public keys (sealed-recipient-..., from `sealed keygen --recipient`) or symmetric keys by id (key:<id>, printed by `sealed keygen`)."
+)]
+pub struct RecipientsArgs {
+    #[command(subcommand)]
+    pub command: RecipientsCommand,
+}
+
+#[derive(Subcommand)]
+pub enum RecipientsCommand {
+    #[command(about = "Print the configured recipients")]
+    List,
+    #[command(about = "Add recipients and rewrap every value in the env file")]
+    Add(RecipientsAddArgs),
+    #[command(about = "Remove recipients and rewrap every value in the env file")]
+    Remove(RecipientsRemoveArgs),
+}
+
+#[derive(Args)]
+#[command(
+    long_about = "Add recipients to the project config and re-encrypt every value in the env file to the new list with fresh data keys.\nA symmetric key can be added by id (key:<id>, together with the key itself as a key source) or by file with --recipient-key-file, which adds key:<id> for the key in that file.\nDecrypting the existing values needs a key or identity that is one of their recipients (--key/--key-file/--key-stdin/--key-fd/--key-command, a passphrase, --identity, SEALED_KEY, or SEALED_IDENTITY).\nNothing is written if any value fails to decrypt.",
+    group(
+        ArgGroup::new("new_recipients")
+            .required(true)
+            .multiple(true)
+            .args(["recipients", "recipient_key_files"])
+    )
+)]
+pub struct RecipientsAddArgs {
+    #[arg(value_name = "RECIPIENT", help = "sealed-recipient-... or key:<id>")]
+    pub recipients: Vec<String>,
+
+    #[arg(
+        long = "recipient-key-file",
+        short = 'R',
+        value_name = "PATH",
+        help = "Add the base64 key in PATH as a key:<id> recipient (repeatable)"
     )]
-    pub key: Option<String>,
+    pub recipient_key_files: Vec<PathBuf>,
 
     #[arg(
-        long = "key-file",
-        short = 'K',
+        long = "env-file",
+        short = 'e',
         value_name = "PATH",
-        help = "Read key from a file (base64)"
+        default_value = ".env",
+        help = "Path to env file"
     )]
-    pub key_file: Option<PathBuf>,
+    pub env_file: PathBuf,
 
-    #[arg(long = "key-stdin", short = 'S', help = "Read key from stdin (base64)")]
-    pub key_stdin: bool,
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Context (environment name) the values are bound to [default: from [contexts] in the project config]"
+    )]
+    pub context: Option<String>,
+
+    #[command(flatten)]
+    pub key_args: KeyArgs,
 }
 
 #[derive(Args)]
-pub struct KeygenArgs {
+#[command(
+    long_about = "Remove recipients from the project config and re-encrypt every value in the env file to the remaining recipients with fresh data keys.\nDecrypting the existing values needs a key or identity that is one of their recipients (--key/--key-file/--key-stdin/--key-fd/--key-command, a passphrase, --identity, SEALED_KEY, or SEALED_IDENTITY). Wrapping for a remaining key:<id> recipient needs that key.\nNothing is written if any value fails to decrypt. Removed members may have seen the old plaintexts; rotate those secrets as well."
+)]
+pub struct RecipientsRemoveArgs {
     #[arg(
-        long = "out-file",
+        value_name = "RECIPIENT",
+        required = true,
+        help = "sealed-recipient-... or key:<id>"
+    )]
+    pub recipients: Vec<String>,
+
+    #[arg(
+        long = "env-file",
+        short = 'e',
+        value_name = "PATH",
+        default_value = ".env",
+        help = "Path to env file"
+    )]
+    pub env_file: PathBuf,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Context (environment name) the values are bound to [default: from [contexts] in the project config]"
+    )]
+    pub context: Option<String>,
+
+    #[command(flatten)]
+    pub key_args: KeyArgs,
+}
+
+#[derive(Args)]
+#[command(
+    long_about = "Check the env file for CI: every encrypted value must be well-formed (valid base64, 12-byte nonce, known algorithm), decrypt with the provided keys in the env file's context, and decrypt to UTF-8. Values starting with ENCv or ENCr that do not parse (unknown version, truncated) are reported as malformed. Prints a report per variable (text or JSON); plaintext is never printed.\nIf the env file has a sealed-mac trailer, an HMAC over every variable name and value in file order, it must match. The trailer is maintained by set, unset, rekey and the recipients commands, and detects variables that were deleted, reordered or replaced with older ciphertexts; it is required if the project config sets mac = true.\nExits with status 2 if any check fails.\nWith --update-mac, accept the current content (e.g. after reviewing a manual edit) and recompute the trailer with the first key provided."
+)]
+pub struct VerifyArgs {
+    #[arg(
+        long = "env-file",
+        short = 'e',
+        value_name = "PATH",
+        default_value = ".env",
+        help = "Path to env file"
+    )]
+    pub env_file: PathBuf,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Context (environment name) the values are bound to [default: from [contexts] in the project config]"
+    )]
+    pub context: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value = "text",
+        help = "Output format"
+    )]
+    pub format: OutputFormat,
+
+    #[arg(
+        long = "update-mac",
+        help = "Recompute the sealed-mac trailer instead of checking the file"
+    )]
+    pub update_mac: bool,
+
+    #[command(flatten)]
+    pub key_args: KeyArgs,
+}
+
+#[derive(Args)]
+#[command(
+    long_about = "Check the env file against [policy] in the project config: variables matching `encrypted` must be encrypted, and variables matching `plain` must stay plain. Entries are names or glob patterns (* and ?); an exact name takes precedence over a pattern, and a variable matching both lists must be encrypted.\nNo key is needed. Prints every violation with its line number and exits with status 6 if there are any."
+)]
+pub struct CheckArgs {
+    #[arg(
+        long = "env-file",
+        short = 'e',
+        value_name = "PATH",
+        default_value = ".env",
+        help = "Path to env file"
+    )]
+    pub env_file: PathBuf,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value = "text",
+        help = "Output format"
+    )]
+    pub format: OutputFormat,
+}
+
+#[derive(Args)]
+#[command(
+    long_about = "Scan env files for plaintext values that look like credentials: private keys (PEM), known token prefixes (AWS, GitHub, Stripe, Slack), URLs with an embedded password, and high-entropy strings (at least 24 characters mixing upper case, lower case and digits, or long hex strings; URLs, paths and word sequences are ignored). Encrypted values are skipped, and values are never printed.\nVariables listed in [scan] allow of the project config or with --allow (names or glob patterns), and variables the [policy] requires to stay plain, are not reported.\nPrints every finding with its file and line number and exits with status 6 if there are any, so it can run as a pre-commit hook."
+)]
+pub struct ScanArgs {
+    #[arg(
+        value_name = "ENV_FILE",
+        default_value = ".env",
+        help = "Env files to scan"
+    )]
+    pub env_files: Vec<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        help = "Do not report variables matching this name or glob pattern (repeatable)"
+    )]
+    pub allow: Vec<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value = "text",
+        help = "Output format"
+    )]
+    pub format: OutputFormat,
+}
+
+#[derive(Args)]
+#[command(
+    long_about = "Encrypt every plaintext value in the env file in place, as `sealed set` would, keeping keys, comments and order. Empty values and variables the [policy] requires to stay plain are left alone; --only and --policy narrow the selection further.\nKey input: as for `sealed set`; no key is needed with --dry-run.\nThe env file is read once and replaced atomically."
+)]
+pub struct SealArgs {
+    #[arg(
+        long = "env-file",
+        short = 'e',
+        value_name = "PATH",
+        default_value = ".env",
+        help = "Path to env file"
+    )]
+    pub env_file: PathBuf,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Context (environment name) to bind the values to [default: from [contexts] in the project config]"
+    )]
+    pub context: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        help = "Only encrypt variables matching this name or glob pattern (repeatable)"
+    )]
+    pub only: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Only encrypt variables the [policy] in the project config requires to be encrypted"
+    )]
+    pub policy: bool,
+
+    #[arg(
+        long = "dry-run",
+        short = 'n',
+        help = "List the variables that would be encrypted without changing the file"
+    )]
+    pub dry_run: bool,
+
+    #[command(flatten)]
+    pub key_args: KeyArgs,
+}
+
+#[derive(Args)]
+#[command(
+    long_about = "Decrypt every encrypted value in the env file and write a plaintext copy for tools that need one (docker compose, IDE run configurations), keeping keys, comments and order.\nThe output file is created with permissions 0600. Files tracked by git, and the env file itself, are never overwritten. Without --output, the copy is printed to stdout only with --reveal.\nA key is required only if the env file contains encrypted values (from --key/--key-file/--key-stdin/--key-fd/--key-command, a passphrase, SEALED_KEY, or key_command in the project config; several keys can be given with repeated --key, --keyring-file, or SEALED_KEYS; recipient-encrypted values need --identity or SEALED_IDENTITY)."
+)]
+pub struct UnsealArgs {
+    #[arg(
+        long = "env-file",
+        short = 'e',
+        value_name = "PATH",
+        default_value = ".env",
+        help = "Path to env file"
+    )]
+    pub env_file: PathBuf,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "Context (environment name) the values are bound to [default: from [contexts] in the project config]"
+    )]
+    pub context: Option<String>,
+
+    #[arg(
+        long,
         short = 'o',
         value_name = "PATH",
-        help = "Write base64 key to a file instead of stdout"
+        help = "Write the decrypted copy to this file (mode 0600)"
     )]
-    pub out_file: Option<PathBuf>,
+    pub output: Option<PathBuf>,
+
+    #[arg(
+        long,
+        short = 'r',
+        conflicts_with = "output",
+        help = "Print the decrypted copy to stdout"
+    )]
+    pub reveal: bool,
+
+    #[command(flatten)]
+    pub key_args: KeyArgs,
+}
+
+#[derive(Args)]
+#[command(
+    long_about = "When a variable is assigned more than once, the last assignment takes effect. Remove every earlier one, so that every tool reading the env file agrees on the value. The remaining lines, including the effective assignments, are kept as they are.\nIf the env file has a sealed-mac trailer (or the project config sets mac = true), a key is required to verify and update it; no key is needed with --dry-run."
+)]
+pub struct DedupeArgs {
+    #[arg(
+        long = "env-file",
+        short = 'e',
+        value_name = "PATH",
+        default_value = ".env",
+        help = "Path to env file"
+    )]
+    pub env_file: PathBuf,
+
+    #[arg(
+        long = "dry-run",
+        short = 'n',
+        help = "List the duplicate assignments that would be removed without changing the file"
+    )]
+    pub dry_run: bool,
+
+    #[command(flatten)]
+    pub key_args: KeyArgs,
 }
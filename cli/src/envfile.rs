@@ -1,111 +1,501 @@
-use std::fs;
-use std::io;
-use std::path::Path;
+use sealed_core::dotenv::{Document, Duplicate, EnvVar, parse_vars};
+use sealed_core::mac::{FileMac, is_mac_node, with_mac};
+use sealed_core::{Key, Keyring};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use zeroize::Zeroizing;
 
 use crate::error::SealedError;
+use crate::input::{KeySources, provide_key_hint};
 
-pub fn read_var(path: &Path, var: &str) -> Result<Option<String>, SealedError> {
-    let content = fs::read_to_string(path).map_err(|e| {
-        SealedError::EnvFile(format!("failed to read env file {}: {}", path.display(), e))
-    })?;
+pub fn read_vars(path: &Path) -> Result<Vec<EnvVar>, SealedError> {
+    let content = read_env_file(path)?;
 
-    let mut last = None;
+    Ok(parse_vars(&content))
+}
 
-    for line in content.lines() {
-        if let Some(parsed) = parse_var_line(line)
-            && parsed.key == var
-        {
-            last = Some(parsed.value);
-        }
+/// Parse the env file after checking it against its `# sealed-mac:` trailer. With
+/// `require_mac`, a file without a trailer is rejected as well.
+pub fn read_verified_vars(
+    path: &Path,
+    keyring: &Keyring,
+    require_mac: bool,
+) -> Result<Vec<EnvVar>, SealedError> {
+    let content = read_env_file(path)?;
+
+    if verify_content(path, &content, keyring)?.is_none() && require_mac {
+        return Err(mac_error(path, sealed_core::Error::MissingMac));
+    }
+
+    Ok(parse_vars(&content))
+}
+
+/// How a read-modify-write maintains the env file's `# sealed-mac:` trailer.
+///
+/// The trailer is verified against the content read under the lock, so a change made after
+/// it was last computed is never folded into a fresh trailer.
+#[derive(Clone, Copy)]
+pub struct MacUpdate<'a> {
+    /// The keys the current trailer may have been computed with.
+    pub keyring: &'a Keyring,
+    /// Add a trailer to a file that has none.
+    pub required: bool,
+    /// Recompute the trailer with this key instead of the one that computed it.
+    pub new_key: Option<&'a Key>,
+}
+
+pub fn upsert_var(
+    path: &Path,
+    var: &str,
+    value: &str,
+    mac: MacUpdate<'_>,
+) -> Result<(), SealedError> {
+    // Fail before `lock` creates a missing file, rather than leave an empty one behind.
+    if !path.exists() {
+        mac_key(path, "", mac)?;
+    }
+
+    let _lock = lock(path, true)?;
+    let content = read_env_file(path)?;
+    let mac_key = mac_key(path, &content, mac)?;
+
+    let mut document = Document::parse(&content);
+    document.set(var, value);
+
+    write_atomic(path, &finish(document.to_string(), mac_key))
+}
+
+pub fn remove_var(path: &Path, var: &str, mac: MacUpdate<'_>) -> Result<bool, SealedError> {
+    let _lock = lock(path, false)?;
+    let content = read_env_file(path)?;
+    let mac_key = mac_key(path, &content, mac)?;
+
+    let mut document = Document::parse(&content);
+    if !document.remove(var) {
+        return Ok(false);
+    }
+
+    write_atomic(path, &finish(document.to_string(), mac_key))?;
+
+    Ok(true)
+}
+
+/// Remove assignments overridden by a later one to the same key, returning the duplicates.
+pub fn dedupe_vars(path: &Path, mac: MacUpdate<'_>) -> Result<Vec<Duplicate>, SealedError> {
+    let _lock = lock(path, false)?;
+    let content = read_env_file(path)?;
+    let mac_key = mac_key(path, &content, mac)?;
+
+    let mut document = Document::parse(&content);
+    let duplicates = document.dedupe();
+    if duplicates.is_empty() {
+        return Ok(duplicates);
+    }
+
+    write_atomic(path, &finish(document.to_string(), mac_key))?;
+
+    Ok(duplicates)
+}
+
+pub fn rewrite_vars<F>(path: &Path, mac: MacUpdate<'_>, rewrite: F) -> Result<usize, SealedError>
+where
+    F: FnMut(&str, &str) -> Result<Option<String>, SealedError>,
+{
+    let _lock = lock(path, false)?;
+    let content = read_env_file(path)?;
+    let mac_key = mac_key(path, &content, mac)?;
+
+    let mut document = Document::parse(&content);
+    let rewritten = document.rewrite(rewrite)?;
+    let new_content = finish(document.to_string(), mac_key);
+
+    // Without rewritten values, only a trailer computed with a different key needs writing.
+    if rewritten == 0 && (mac_key.is_none() || new_content == content) {
+        return Ok(0);
     }
 
-    Ok(last)
+    write_atomic(path, &new_content)?;
+
+    Ok(rewritten)
+}
+
+/// A copy of the env file with values replaced by `rewrite`, without its sealed-mac trailer,
+/// and the number of rewritten values. The trailer is verified with `keyring` first.
+pub fn rewritten_copy<F>(
+    path: &Path,
+    keyring: &Keyring,
+    rewrite: F,
+) -> Result<(Zeroizing<String>, usize), SealedError>
+where
+    F: FnMut(&str, &str) -> Result<Option<String>, SealedError>,
+{
+    let content = Zeroizing::new(read_env_file(path)?);
+    verify_content(path, &content, keyring)?;
+
+    let mut document = Document::parse(&content);
+    document.retain(|node| !is_mac_node(node));
+    let rewritten = document.rewrite(rewrite)?;
+
+    Ok((Zeroizing::new(document.to_string()), rewritten))
+}
+
+/// Whether git tracks `path`. Outside a repository, or without git, nothing is tracked.
+pub fn is_git_tracked(path: &Path) -> bool {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return false;
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+
+    Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["ls-files", "--error-unmatch", "--"])
+        .arg(name)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// The env file's `# sealed-mac:` trailer, if it has one. A missing file has none.
+pub fn read_mac(path: &Path) -> Result<Option<FileMac>, SealedError> {
+    match fs::read_to_string(path) {
+        Ok(content) => FileMac::find(&content).map_err(|e| mac_error(path, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(read_error(path, e)),
+    }
+}
+
+/// Recompute the env file's `# sealed-mac:` trailer with `key`, accepting its current content.
+pub fn update_mac(path: &Path, key: &Key) -> Result<(), SealedError> {
+    let _lock = lock(path, false)?;
+    let content = read_env_file(path)?;
+
+    write_atomic(path, &with_mac(&content, key))
+}
+
+/// The error for a sealed-mac trailer that has to be computed without a key.
+pub fn mac_key_required() -> SealedError {
+    SealedError::Arg(format!(
+        "the sealed-mac trailer needs a key; {}",
+        provide_key_hint(KeySources::Keys)
+    ))
+}
+
+// Check `content` against its trailer, returning the trailer if it has one.
+fn verify_content(
+    path: &Path,
+    content: &str,
+    keyring: &Keyring,
+) -> Result<Option<FileMac>, SealedError> {
+    let Some(mac) = FileMac::find(content).map_err(|e| mac_error(path, e))? else {
+        return Ok(None);
+    };
+    mac.verify(keyring, content)
+        .map_err(|e| mac_error(path, e))?;
+
+    Ok(Some(mac))
+}
+
+// Verify the trailer of `content`, read under the lock, and pick the key to recompute it
+// with: `new_key`, the key that computed it, or, for a required trailer, the first key.
+fn mac_key<'a>(
+    path: &Path,
+    content: &str,
+    mac: MacUpdate<'a>,
+) -> Result<Option<&'a Key>, SealedError> {
+    match verify_content(path, content, mac.keyring)? {
+        Some(found) => Ok(mac
+            .new_key
+            .or_else(|| mac.keyring.key_by_id(found.key_id()))),
+        None if mac.required => mac
+            .new_key
+            .or_else(|| mac.keyring.keys().first())
+            .map(Some)
+            .ok_or_else(mac_key_required),
+        None => Ok(None),
+    }
+}
+
+fn finish(content: String, mac_key: Option<&Key>) -> String {
+    match mac_key {
+        Some(key) => with_mac(&content, key),
+        None => content,
+    }
 }
 
-pub fn upsert_var(path: &Path, var: &str, value: &str) -> Result<(), SealedError> {
-    let content = match fs::read_to_string(path) {
-        Ok(c) => c,
-        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
-        Err(e) => {
-            return Err(SealedError::EnvFile(format!(
-                "failed to read env file {}: {}",
-                path.display(),
-                e
-            )));
+fn mac_error(path: &Path, err: sealed_core::Error) -> SealedError {
+    let hint = match err {
+        sealed_core::Error::MacMismatch => {
+            "; review the changes and run `sealed verify --update-mac` to accept them"
         }
+        _ => "",
     };
 
-    let mut lines: Vec<String> = content.lines().map(|l| l.to_string()).collect();
-    let mut replaced = false;
+    SealedError::Crypto(format!("{}: {}{}", path.display(), err, hint))
+}
 
-    for line in &mut lines {
-        if let Some(parsed) = parse_var_line(line)
-            && parsed.key == var
-        {
-            let mut new_line = String::new();
-            new_line.push_str(&parsed.leading_ws);
-            if parsed.export_prefix {
-                new_line.push_str("export ");
-            }
-            new_line.push_str(var);
-            new_line.push('=');
-            new_line.push_str(value);
-            *line = new_line;
-            replaced = true;
+/// An exclusive advisory lock on an env file, released when dropped.
+///
+/// Every read-modify-write of an env file holds it, so concurrent `sealed` processes editing
+/// the same file do not lose each other's updates.
+struct EnvFileLock {
+    _file: File,
+}
+
+/// Lock the env file, waiting for other `sealed` processes to release it. With `create`, a
+/// missing file is created empty first.
+fn lock(path: &Path, create: bool) -> Result<EnvFileLock, SealedError> {
+    let lock_error = |e: io::Error| {
+        SealedError::EnvFile(format!("failed to lock env file {}: {}", path.display(), e))
+    };
+
+    loop {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(create)
+            .create(create)
+            .open(path)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => read_error(path, e),
+                _ => lock_error(e),
+            })?;
+        file.lock().map_err(lock_error)?;
+
+        // Writers replace the file, so the one we waited on may no longer be the env file.
+        if is_current(&file, path).map_err(lock_error)? {
+            return Ok(EnvFileLock { _file: file });
         }
     }
+}
 
-    if !replaced {
-        lines.push(format!("{}={}", var, value));
+#[cfg(unix)]
+fn is_current(file: &File, path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let locked = file.metadata()?;
+    match fs::metadata(path) {
+        Ok(current) => Ok(current.dev() == locked.dev() && current.ino() == locked.ino()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
     }
+}
+
+#[cfg(not(unix))]
+fn is_current(_file: &File, _path: &Path) -> io::Result<bool> {
+    Ok(true)
+}
 
-    let mut new_content = lines.join("\n");
-    new_content.push('\n');
+/// Replace the env file with `content` through a temporary file in the same directory, so
+/// readers and crashes see either the old or the new file. The new file keeps the old one's
+/// permissions and, where the OS allows it, its owner and group. A symlinked env file is
+/// replaced at its target.
+fn write_atomic(path: &Path, content: &str) -> Result<(), SealedError> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let original = fs::metadata(&path).ok();
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+
+    replace_file(&path, content, &options, |file| {
+        let Some(original) = &original else {
+            return Ok(());
+        };
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
 
-    fs::write(path, new_content).map_err(|e| {
+            // Only root can give a file away; otherwise the new file stays ours.
+            let _ = std::os::unix::fs::fchown(file, Some(original.uid()), Some(original.gid()));
+        }
+        file.set_permissions(original.permissions())
+    })
+    .map_err(|e| {
         SealedError::EnvFile(format!(
             "failed to write env file {}: {}",
             path.display(),
             e
         ))
-    })?;
+    })
+}
+
+/// Like `write_atomic`, but the file is created with mode 0600, and an existing file is
+/// replaced rather than reused, so its permissions never apply to the new content.
+pub fn write_private(path: &Path, content: &str) -> Result<(), SealedError> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    replace_file(path, content, &options, |_| Ok(()))
+        .map_err(|e| SealedError::EnvFile(format!("failed to write {}: {}", path.display(), e)))
+}
+
+// Write `content` to a temporary file opened with `options`, fsync it, rename it over `path`
+// and fsync the directory so the rename itself is durable.
+fn replace_file<F>(path: &Path, content: &str, options: &OpenOptions, prepare: F) -> io::Result<()>
+where
+    F: FnOnce(&File) -> io::Result<()>,
+{
+    let tmp_path = temp_path(path);
 
+    let result = options
+        .open(&tmp_path)
+        .and_then(|mut file| {
+            prepare(&file)?;
+            file.write_all(content.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&tmp_path, path));
+
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    sync_dir(path)
+}
+
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> io::Result<()> {
     Ok(())
 }
 
-fn parse_var_line(line: &str) -> Option<ParsedLine> {
-    let trimmed = line.trim_start();
-    if trimmed.is_empty() || trimmed.starts_with('#') {
-        return None;
+fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    path.with_file_name(format!(".{}.sealed-{}.tmp", file_name, std::process::id()))
+}
+
+pub fn read_env_file(path: &Path) -> Result<String, SealedError> {
+    fs::read_to_string(path).map_err(|e| read_error(path, e))
+}
+
+fn read_error(path: &Path, err: io::Error) -> SealedError {
+    SealedError::EnvFile(format!(
+        "failed to read env file {}: {}",
+        path.display(),
+        err
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    // A fresh, empty directory for one test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sealed-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
     }
 
-    let leading_ws = line[..line.len() - trimmed.len()].to_string();
-    let (export_prefix, rest) = if let Some(stripped) = trimmed.strip_prefix("export ") {
-        (true, stripped)
-    } else {
-        (false, trimmed)
-    };
+    fn no_mac(keyring: &Keyring) -> MacUpdate<'_> {
+        MacUpdate {
+            keyring,
+            required: false,
+            new_key: None,
+        }
+    }
+
+    #[test]
+    fn failed_upsert_leaves_no_file_behind() {
+        let dir = temp_dir("failed-upsert");
+        let path = dir.join(".env");
+        let keyring = Keyring::new();
+        let mac = MacUpdate {
+            required: true,
+            ..no_mac(&keyring)
+        };
 
-    let eq = rest.find('=')?;
-    let key = rest[..eq].trim_end();
+        assert!(upsert_var(&path, "A", "1", mac).is_err());
+        assert!(!path.exists());
 
-    if key.is_empty() {
-        return None;
+        upsert_var(&path, "A", "1", no_mac(&keyring)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "A=1\n");
+        fs::remove_dir_all(dir).unwrap();
     }
 
-    let value = rest[eq + 1..].to_string();
+    #[cfg(unix)]
+    #[test]
+    fn write_atomic_keeps_the_mode() {
+        use std::os::unix::fs::PermissionsExt;
 
-    Some(ParsedLine {
-        leading_ws,
-        export_prefix,
-        key: key.to_string(),
-        value,
-    })
-}
+        let dir = temp_dir("keeps-mode");
+        let path = dir.join(".env");
+        for mode in [0o600, 0o640, 0o644] {
+            fs::write(&path, "A=1\n").unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+
+            write_atomic(&path, "A=2\n").unwrap();
+
+            let metadata = fs::metadata(&path).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, mode);
+            assert_eq!(fs::read_to_string(&path).unwrap(), "A=2\n");
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_replaced_file_is_no_longer_current() {
+        let dir = temp_dir("replaced");
+        let path = dir.join(".env");
+        fs::write(&path, "A=1\n").unwrap();
+
+        let old = File::open(&path).unwrap();
+        assert!(is_current(&old, &path).unwrap());
 
-struct ParsedLine {
-    leading_ws: String,
-    export_prefix: bool,
-    key: String,
-    value: String,
+        write_atomic(&path, "A=2\n").unwrap();
+        assert!(!is_current(&old, &path).unwrap());
+        assert!(is_current(&File::open(&path).unwrap(), &path).unwrap());
+
+        fs::remove_file(&path).unwrap();
+        assert!(!is_current(&old, &path).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn waits_for_the_lock_and_relocks_a_replaced_file() {
+        let dir = temp_dir("relock");
+        let path = dir.join(".env");
+        fs::write(&path, "A=1\n").unwrap();
+
+        // Hold the lock while another writer queues up on the current file, then replace
+        // the file the way a writer does before releasing the lock.
+        let held = lock(&path, false).unwrap();
+        let writer = {
+            let path = path.clone();
+            thread::spawn(move || upsert_var(&path, "B", "2", no_mac(&Keyring::new())))
+        };
+        thread::sleep(Duration::from_millis(200));
+        assert!(!writer.is_finished());
+
+        write_atomic(&path, "A=1\nC=3\n").unwrap();
+        drop(held);
+
+        writer.join().unwrap().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "A=1\nC=3\nB=2\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
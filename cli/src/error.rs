@@ -10,6 +10,10 @@ pub enum SealedError {
     VarNotFound(String),
     #[error("{0}")]
     EnvFile(String),
+    #[error("{0}")]
+    Exec(String),
+    #[error("{0}")]
+    Policy(String),
 }
 
 impl SealedError {
@@ -19,6 +23,19 @@ impl SealedError {
             SealedError::Crypto(_) => 2,
             SealedError::Arg(_) => 3,
             SealedError::EnvFile(_) => 4,
+            SealedError::Exec(_) => 5,
+            SealedError::Policy(_) => 6,
+        }
+    }
+}
+
+impl From<sealed_core::Error> for SealedError {
+    fn from(err: sealed_core::Error) -> Self {
+        match err {
+            sealed_core::Error::KeyUnavailable(_) | sealed_core::Error::Config(_) => {
+                SealedError::Arg(err.to_string())
+            }
+            _ => SealedError::Crypto(err.to_string()),
         }
     }
 }
@@ -1,19 +1,53 @@
-use secrecy::{SecretSlice, SecretString};
+use sealed_core::config::Config;
+use sealed_core::passphrase::{PassphraseKey, PassphraseParams};
+use sealed_core::provider::{CommandKey, FileKey, KeyProvider};
+use sealed_core::{Identity, Key, Keyring};
+use secrecy::{ExposeSecret, SecretString};
 use std::env;
 use std::fs;
 use std::io::{self, Read};
 use std::path::PathBuf;
 use zeroize::Zeroize;
 
-use crate::cli::SetArgs;
-use crate::crypto::decode_key;
+use crate::cli::{KeyArgs, NewKeyArgs, SetArgs};
 use crate::error::SealedError;
 
 pub enum KeyInput {
     Direct(String),
+    Keys(Vec<String>),
     File(PathBuf),
+    KeyringFile(PathBuf),
     Stdin,
+    Fd(u32),
+    Command(String),
     Env(String),
+    EnvKeys(String),
+    /// `SEALED_KEY` and `SEALED_KEYS` together: decrypt with all of them, encrypt with
+    /// `SEALED_KEY`.
+    EnvKeyring(String, String),
+    Passphrase(PassphraseInput, PassphraseParams),
+}
+
+pub enum PassphraseInput {
+    File(PathBuf),
+    Stdin,
+    Env(String),
+}
+
+pub enum IdentityInput {
+    File(PathBuf),
+    Env(String),
+}
+
+pub struct KeyringInput {
+    keys: Option<KeyInput>,
+    identity: Option<IdentityInput>,
+}
+
+impl KeyringInput {
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_none() && self.identity.is_none()
+    }
 }
 
 pub fn read_value(args: &mut SetArgs) -> Result<SecretString, SealedError> {
@@ -70,62 +104,244 @@ pub fn read_value(args: &mut SetArgs) -> Result<SecretString, SealedError> {
     ))
 }
 
-pub fn select_key_input(
-    key: Option<String>,
-    key_file: Option<PathBuf>,
-    key_stdin: bool,
-) -> Result<Option<KeyInput>, SealedError> {
+/// The key sources a command accepts, for the hint in "key required" errors.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum KeySources {
+    /// A single key to encrypt with.
+    Single,
+    /// One or more keys (for decrypting symmetric values or the sealed-mac trailer).
+    Keys,
+    /// Keys and an X25519 identity (for decrypting any value).
+    KeysAndIdentity,
+}
+
+/// "provide --key, ..., or set SEALED_KEY, ..." for the sources `sources` accepts.
+pub fn provide_key_hint(sources: KeySources) -> String {
+    let mut options = vec!["--key", "--key-file"];
+    let mut vars = vec!["SEALED_KEY"];
+    if sources != KeySources::Single {
+        options.push("--keyring-file");
+        vars.push("SEALED_KEYS");
+    }
+    options.extend([
+        "--key-stdin",
+        "--key-fd",
+        "--key-command",
+        "--passphrase-file",
+        "--passphrase-stdin",
+    ]);
+    vars.push("SEALED_PASSPHRASE");
+    if sources == KeySources::KeysAndIdentity {
+        options.push("--identity");
+        vars.push("SEALED_IDENTITY");
+    }
+
+    let last = vars.pop().unwrap_or_default();
+    format!(
+        "provide {}, or set {} or {}",
+        options.join(", "),
+        vars.join(", "),
+        last
+    )
+}
+
+pub fn select_keyring_input(
+    mut args: KeyArgs,
+    config: &Config,
+) -> Result<KeyringInput, SealedError> {
+    let env_identity = env::var("SEALED_IDENTITY").ok().filter(|s| !s.is_empty());
+
+    let identity = match (args.identity.take(), env_identity) {
+        (Some(_), Some(_)) => {
+            return Err(SealedError::Arg(
+                "choose exactly one identity source: --identity or SEALED_IDENTITY".to_string(),
+            ));
+        }
+        (Some(path), None) => Some(IdentityInput::File(path)),
+        (None, Some(identity)) => Some(IdentityInput::Env(identity)),
+        (None, None) => None,
+    };
+
+    Ok(KeyringInput {
+        keys: select_key_input(args, config)?,
+        identity,
+    })
+}
+
+pub fn select_key_input(args: KeyArgs, config: &Config) -> Result<Option<KeyInput>, SealedError> {
+    let KeyArgs {
+        mut key,
+        key_file,
+        keyring_file,
+        key_stdin,
+        key_fd,
+        key_command,
+        passphrase_file,
+        passphrase_stdin,
+        identity: _,
+    } = args;
     let env_key = env::var("SEALED_KEY").ok().filter(|s| !s.is_empty());
+    let env_keys = env::var("SEALED_KEYS").ok().filter(|s| !s.is_empty());
+    let env_passphrase = env::var("SEALED_PASSPHRASE").ok().filter(|s| !s.is_empty());
 
     let mut count = 0;
 
-    if key.is_some() {
+    if !key.is_empty() {
         count += 1;
     }
     if key_file.is_some() {
         count += 1;
     }
+    if keyring_file.is_some() {
+        count += 1;
+    }
     if key_stdin {
         count += 1;
     }
-    if env_key.is_some() {
+    if key_fd.is_some() {
+        count += 1;
+    }
+    if key_command.is_some() {
+        count += 1;
+    }
+    if passphrase_file.is_some() {
+        count += 1;
+    }
+    if passphrase_stdin {
+        count += 1;
+    }
+    // SEALED_KEY and SEALED_KEYS are one source, merged as in `Sealed::from_env`.
+    if env_key.is_some() || env_keys.is_some() {
+        count += 1;
+    }
+    if env_passphrase.is_some() {
         count += 1;
     }
 
     if count > 1 {
         return Err(SealedError::Arg(
-            "choose exactly one key source: --key, --key-file, --key-stdin, or SEALED_KEY"
+            "choose exactly one key source: --key (repeatable), --key-file, --keyring-file, --key-stdin, --key-fd, --key-command, --passphrase-file, --passphrase-stdin, SEALED_KEY and/or SEALED_KEYS, or SEALED_PASSPHRASE"
                 .to_string(),
         ));
     }
 
-    if let Some(k) = key {
-        return Ok(Some(KeyInput::Direct(k)));
+    let passphrase = if let Some(pf) = passphrase_file {
+        Some(PassphraseInput::File(pf))
+    } else if passphrase_stdin {
+        Some(PassphraseInput::Stdin)
+    } else {
+        env_passphrase.map(PassphraseInput::Env)
+    };
+
+    if let Some(passphrase) = passphrase {
+        let params = config.passphrase.clone().ok_or_else(|| {
+            SealedError::Arg(
+                "passphrase requires a [passphrase] salt in the project config; run `sealed keygen --passphrase`"
+                    .to_string(),
+            )
+        })?;
+        return Ok(Some(KeyInput::Passphrase(passphrase, params)));
+    }
+
+    if key.len() == 1 {
+        return Ok(key.pop().map(KeyInput::Direct));
+    }
+    if !key.is_empty() {
+        return Ok(Some(KeyInput::Keys(key)));
     }
     if let Some(kf) = key_file {
         return Ok(Some(KeyInput::File(kf)));
     }
+    if let Some(kf) = keyring_file {
+        return Ok(Some(KeyInput::KeyringFile(kf)));
+    }
     if key_stdin {
         return Ok(Some(KeyInput::Stdin));
     }
-    if let Some(ek) = env_key {
-        return Ok(Some(KeyInput::Env(ek)));
+    if let Some(fd) = key_fd {
+        return Ok(Some(KeyInput::Fd(fd)));
+    }
+    if let Some(cmd) = key_command {
+        return Ok(Some(KeyInput::Command(cmd)));
+    }
+    match (env_key, env_keys) {
+        (Some(ek), Some(eks)) => return Ok(Some(KeyInput::EnvKeyring(ek, eks))),
+        (Some(ek), None) => return Ok(Some(KeyInput::Env(ek))),
+        (None, Some(eks)) => return Ok(Some(KeyInput::EnvKeys(eks))),
+        (None, None) => {}
+    }
+    if let Some(cmd) = &config.key_command {
+        return Ok(Some(KeyInput::Command(cmd.clone())));
     }
 
     Ok(None)
 }
 
-pub fn read_key(input: KeyInput) -> Result<SecretSlice<u8>, SealedError> {
+pub fn select_new_key_input(args: NewKeyArgs) -> Result<KeyInput, SealedError> {
+    let NewKeyArgs {
+        new_key,
+        new_key_file,
+        new_key_stdin,
+    } = args;
+
+    let count = [new_key.is_some(), new_key_file.is_some(), new_key_stdin]
+        .into_iter()
+        .filter(|set| *set)
+        .count();
+
+    if count != 1 {
+        return Err(SealedError::Arg(
+            "new key required; choose exactly one of --new-key, --new-key-file, or --new-key-stdin"
+                .to_string(),
+        ));
+    }
+
+    if let Some(k) = new_key {
+        return Ok(KeyInput::Direct(k));
+    }
+    if let Some(kf) = new_key_file {
+        return Ok(KeyInput::File(kf));
+    }
+
+    Ok(KeyInput::Stdin)
+}
+
+pub fn read_key(input: KeyInput) -> Result<Key, SealedError> {
     let b64 = match input {
+        KeyInput::Keys(_) | KeyInput::KeyringFile(_) | KeyInput::EnvKeys(_) => {
+            return Err(SealedError::Arg(
+                "this command encrypts with a single key; --key may be given only once, and --keyring-file and SEALED_KEYS can only be used for decrypting"
+                    .to_string(),
+            ));
+        }
         KeyInput::Direct(s) => SecretString::from(s),
-        KeyInput::Env(s) => SecretString::from(s),
-        KeyInput::File(path) => {
-            let mut raw = fs::read_to_string(&path).map_err(|e| {
-                SealedError::Arg(format!("failed to read key file {}: {}", path.display(), e))
-            })?;
-            let trimmed = trim_end_newlines(&raw).to_string();
-            raw.zeroize();
-            SecretString::from(trimmed)
+        KeyInput::Env(s) | KeyInput::EnvKeyring(s, _) => SecretString::from(s),
+        KeyInput::File(path) => return Ok(FileKey::new(path).provide()?),
+        KeyInput::Command(cmd) => return Ok(CommandKey::new(cmd).provide()?),
+        KeyInput::Fd(fd) => return read_fd_key(fd),
+        KeyInput::Passphrase(input, params) => {
+            let passphrase = match input {
+                PassphraseInput::Env(s) => SecretString::from(s),
+                PassphraseInput::File(path) => {
+                    let mut raw = fs::read_to_string(&path).map_err(|e| {
+                        SealedError::Arg(format!(
+                            "failed to read passphrase file {}: {}",
+                            path.display(),
+                            e
+                        ))
+                    })?;
+                    let trimmed = trim_end_newlines(&raw).to_string();
+                    raw.zeroize();
+                    SecretString::from(trimmed)
+                }
+                PassphraseInput::Stdin => {
+                    let mut raw = read_stdin().map_err(SealedError::Arg)?;
+                    let trimmed = trim_end_newlines(&raw).to_string();
+                    raw.zeroize();
+                    SecretString::from(trimmed)
+                }
+            };
+            return Ok(PassphraseKey::new(passphrase, params).provide()?);
         }
         KeyInput::Stdin => {
             let mut raw = read_stdin().map_err(SealedError::Arg)?;
@@ -135,7 +351,59 @@ pub fn read_key(input: KeyInput) -> Result<SecretSlice<u8>, SealedError> {
         }
     };
 
-    decode_key(&b64)
+    Ok(Key::from_base64(b64.expose_secret())?)
+}
+
+pub fn read_keyring(input: KeyringInput) -> Result<Keyring, SealedError> {
+    let mut keyring = Keyring::new();
+
+    match input.keys {
+        Some(KeyInput::Keys(keys)) => {
+            for key in keys {
+                keyring.add_key(Key::from_base64(SecretString::from(key).expose_secret())?);
+            }
+        }
+        Some(KeyInput::KeyringFile(path)) => keyring.extend(Keyring::from_file(&path)?),
+        Some(KeyInput::EnvKeys(s)) => {
+            keyring.extend(SecretString::from(s).expose_secret().parse::<Keyring>()?)
+        }
+        Some(KeyInput::EnvKeyring(key, keys)) => {
+            keyring.add_key(Key::from_base64(SecretString::from(key).expose_secret())?);
+            keyring.extend(
+                SecretString::from(keys)
+                    .expose_secret()
+                    .parse::<Keyring>()?,
+            )
+        }
+        Some(input) => keyring.add_key(read_key(input)?),
+        None => {}
+    }
+
+    match input.identity {
+        Some(IdentityInput::File(path)) => keyring.add_identity(Identity::from_file(&path)?),
+        Some(IdentityInput::Env(mut s)) => {
+            let identity = s.parse::<Identity>();
+            s.zeroize();
+            keyring.add_identity(identity?);
+        }
+        None => {}
+    }
+
+    Ok(keyring)
+}
+
+#[cfg(unix)]
+fn read_fd_key(fd: u32) -> Result<Key, SealedError> {
+    use sealed_core::provider::FdKey;
+
+    Ok(FdKey::new(fd).provide()?)
+}
+
+#[cfg(not(unix))]
+fn read_fd_key(_fd: u32) -> Result<Key, SealedError> {
+    Err(SealedError::Arg(
+        "--key-fd is only supported on Unix".to_string(),
+    ))
 }
 
 fn read_stdin() -> Result<String, String> {
@@ -1,23 +1,41 @@
-use base64::Engine as _;
-use base64::engine::general_purpose;
 use clap::Parser;
-use rand::TryRngCore;
-use rand::rngs::OsRng;
-use secrecy::ExposeSecret;
+use sealed_core::config::{CONFIG_FILE, Config};
+use sealed_core::dotenv::{Duplicate, EnvVar, duplicates, parse_vars};
+use sealed_core::mac::FileMac;
+use sealed_core::passphrase::PassphraseParams;
+use sealed_core::policy::{Rule, glob_match};
+use sealed_core::{
+    Identity, Key, Keyring, Recipient, SealedValue, encrypt_value, encrypt_value_to, is_encrypted,
+};
+use secrecy::{ExposeSecret, SecretString};
+use serde::Serialize;
 use std::fs;
-use zeroize::Zeroize;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use zeroize::Zeroizing;
 
-use crate::cli::{Cli, Commands, GetArgs, KeygenArgs, SetArgs};
-use crate::crypto::{decrypt_value, encrypt_value, is_encrypted};
-use crate::envfile::{read_var, upsert_var};
+use crate::cli::{
+    CheckArgs, Cli, Commands, DedupeArgs, GetArgs, KeyArgs, KeygenArgs, ListArgs, OutputFormat,
+    RecipientsArgs, RecipientsCommand, RekeyArgs, RunArgs, ScanArgs, SealArgs, SetArgs, UnsealArgs,
+    UnsetArgs, VerifyArgs,
+};
+use crate::envfile::{
+    MacUpdate, dedupe_vars, is_git_tracked, mac_key_required, read_env_file, read_mac, read_vars,
+    read_verified_vars, remove_var, rewrite_vars, rewritten_copy, update_mac, upsert_var,
+    write_private,
+};
 use crate::error::SealedError;
-use crate::input::{read_key, read_value, select_key_input};
+use crate::input::{
+    KeyInput, KeySources, provide_key_hint, read_key, read_keyring, read_value, select_key_input,
+    select_keyring_input, select_new_key_input,
+};
 
 mod cli;
-mod crypto;
 mod envfile;
 mod error;
 mod input;
+mod scan;
 
 fn main() {
     let code = match run() {
@@ -33,71 +51,361 @@ fn main() {
 
 fn run() -> Result<(), SealedError> {
     let cli = Cli::parse();
+    let config = match &cli.config {
+        Some(path) => Config::load(path)?,
+        None => Config::load_or_default(Path::new(CONFIG_FILE))?,
+    };
+    let config_path = cli.config.unwrap_or_else(|| PathBuf::from(CONFIG_FILE));
 
     match cli.command {
-        Commands::Set(args) => run_set(args),
-        Commands::Get(args) => run_get(args),
-        Commands::Keygen(args) => run_keygen(args),
+        Commands::Set(args) => run_set(args, &config),
+        Commands::Get(args) => run_get(args, &config),
+        Commands::Keygen(args) => run_keygen(args, &config, &config_path),
+        Commands::Run(args) => run_run(args, &config),
+        Commands::List(args) => run_list(args, &config),
+        Commands::Unset(args) => run_unset(args, &config),
+        Commands::Rekey(args) => run_rekey(args, &config),
+        Commands::Recipients(args) => run_recipients(args, &config, &config_path),
+        Commands::Verify(args) => run_verify(args, &config),
+        Commands::Check(args) => run_check(args, &config),
+        Commands::Scan(args) => run_scan(args, &config),
+        Commands::Seal(args) => run_seal(args, &config),
+        Commands::Unseal(args) => run_unseal(args, &config),
+        Commands::Dedupe(args) => run_dedupe(args, &config),
     }
 }
 
-fn run_set(args: SetArgs) -> Result<(), SealedError> {
+fn run_set(args: SetArgs, config: &Config) -> Result<(), SealedError> {
     let mut args = args;
 
-    if args.stdin && args.key_stdin {
+    if args.stdin && args.key_args.uses_stdin() {
         return Err(SealedError::Arg(
-            "stdin may be used only once; --stdin and --key-stdin cannot be used together"
+            "stdin may be used only once; --stdin cannot be combined with --key-stdin or --passphrase-stdin"
                 .to_string(),
         ));
     }
 
     let plaintext = read_value(&mut args)?;
-    let key_input =
-        select_key_input(args.key, args.key_file, args.key_stdin)?.ok_or_else(|| {
-            SealedError::Arg(
-                "key required; provide --key, --key-file, --key-stdin, or set SEALED_KEY"
-                    .to_string(),
-            )
+
+    let sealer = Sealer::new(args.key_args, config, &args.env_file)?;
+    let mac = mac_update(&args.env_file, config, &sealer.keyring)?;
+    let context = context_for(args.context.as_deref(), config, &args.env_file);
+    let encrypted = sealer.encrypt(context, &args.var_name, &plaintext)?;
+
+    upsert_var(&args.env_file, &args.var_name, &encrypted, mac)?;
+
+    Ok(())
+}
+
+/// Key material for encrypting new values: the project's recipients if it lists any,
+/// otherwise a single key.
+struct Sealer {
+    recipients: Vec<Recipient>,
+    keyring: Keyring,
+}
+
+impl Sealer {
+    fn new(key_args: KeyArgs, config: &Config, env_file: &Path) -> Result<Self, SealedError> {
+        if !config.recipients.is_empty() {
+            let recipients = config.recipients()?;
+            // Only key:<id> recipients and the sealed-mac trailer need key material.
+            let keyring = if recipients.iter().any(|r| r.key_id().is_some())
+                || mac_required(env_file, config)?
+            {
+                read_keyring(select_keyring_input(key_args, config)?)?
+            } else {
+                Keyring::new()
+            };
+
+            return Ok(Sealer {
+                recipients,
+                keyring,
+            });
+        }
+
+        let key_input = select_key_input(key_args, config)?.ok_or_else(|| {
+            SealedError::Arg(format!(
+                "key required; {}",
+                provide_key_hint(KeySources::Single)
+            ))
         })?;
 
-    let key = read_key(key_input)?;
-    let encrypted = encrypt_value(&key, &args.var_name, &plaintext)?;
+        Ok(Sealer {
+            recipients: Vec::new(),
+            keyring: Keyring::from(read_key(key_input)?),
+        })
+    }
+
+    fn encrypt(
+        &self,
+        context: Option<&str>,
+        var_name: &str,
+        plaintext: &SecretString,
+    ) -> Result<String, SealedError> {
+        let encrypted = if self.recipients.is_empty() {
+            encrypt_value(&self.keyring.keys()[0], context, var_name, plaintext)?
+        } else {
+            encrypt_value_to(
+                &self.recipients,
+                &self.keyring,
+                context,
+                var_name,
+                plaintext,
+            )?
+        };
+
+        Ok(encrypted)
+    }
+}
+
+fn run_seal(args: SealArgs, config: &Config) -> Result<(), SealedError> {
+    let selected = |name: &str, value: &str| {
+        let rule = config.policy.rule(name);
+
+        !value.is_empty()
+            && !is_encrypted(value)
+            && rule != Some(Rule::Plain)
+            && (!args.policy || rule == Some(Rule::Encrypted))
+            && (args.only.is_empty() || args.only.iter().any(|p| glob_match(p, name)))
+    };
+
+    if args.dry_run {
+        let vars: Vec<EnvVar> = read_vars(&args.env_file)?
+            .into_iter()
+            .filter(|var| selected(&var.key, &var.value))
+            .collect();
+        let line_width = vars.iter().map(|var| var.line.to_string().len()).max();
+
+        for var in &vars {
+            println!(
+                "{:>lw$}  {}",
+                var.line,
+                var.key,
+                lw = line_width.unwrap_or(0)
+            );
+        }
+        eprintln!("would encrypt {}", count_vars(vars.len()));
+        return Ok(());
+    }
+
+    let sealer = Sealer::new(args.key_args, config, &args.env_file)?;
+    let mac = mac_update(&args.env_file, config, &sealer.keyring)?;
+    let context = context_for(args.context.as_deref(), config, &args.env_file);
 
-    upsert_var(&args.env_file, &args.var_name, &encrypted)?;
+    let sealed = rewrite_vars(&args.env_file, mac, |name, value| {
+        if !selected(name, value) {
+            return Ok(None);
+        }
+
+        let plaintext = SecretString::from(value);
+        Ok(Some(sealer.encrypt(context, name, &plaintext)?))
+    })?;
 
+    eprintln!("encrypted {}", count_vars(sealed));
     Ok(())
 }
 
-fn run_get(args: GetArgs) -> Result<(), SealedError> {
-    let value = read_var(&args.env_file, &args.var_name)?.ok_or_else(|| {
-        SealedError::VarNotFound(format!(
-            "variable '{}' not found in {}",
-            args.var_name,
-            args.env_file.display()
-        ))
+fn run_unseal(args: UnsealArgs, config: &Config) -> Result<(), SealedError> {
+    if args.output.is_none() && !args.reveal {
+        return Err(SealedError::Arg(
+            "refusing to print plaintext; write it to a file with --output or pass --reveal"
+                .to_string(),
+        ));
+    }
+    if let Some(output) = &args.output {
+        if is_git_tracked(output) {
+            return Err(SealedError::Arg(format!(
+                "refusing to overwrite {}: it is tracked by git",
+                output.display()
+            )));
+        }
+        if same_file(output, &args.env_file) {
+            return Err(SealedError::Arg(format!(
+                "refusing to overwrite the env file {} with plaintext",
+                output.display()
+            )));
+        }
+    }
+
+    let vars = read_vars(&args.env_file)?;
+    let context = context_for(args.context.as_deref(), config, &args.env_file);
+    let keyring_input = select_keyring_input(args.key_args, config)?;
+    let needs_key =
+        read_mac(&args.env_file)?.is_some() || vars.iter().any(|var| is_encrypted(&var.value));
+    if keyring_input.is_empty() && needs_key {
+        return Err(SealedError::Crypto(format!(
+            "env file contains encrypted values; {}",
+            provide_key_hint(KeySources::KeysAndIdentity)
+        )));
+    }
+    let keyring = if needs_key {
+        read_keyring(keyring_input)?
+    } else {
+        Keyring::new()
+    };
+
+    let (content, count) = rewritten_copy(&args.env_file, &keyring, |name, value| {
+        if !is_encrypted(value) {
+            return Ok(None);
+        }
+
+        let plaintext = keyring
+            .decrypt(context, name, value)
+            .map_err(|e| SealedError::Crypto(format!("{}: {}", name, e)))?;
+        Ok(Some(plaintext.expose_secret().to_string()))
     })?;
 
-    if !is_encrypted(&value) {
-        println!("{}", value);
+    match &args.output {
+        Some(output) => {
+            write_private(output, &content)?;
+            eprintln!("decrypted {} to {}", count_vars(count), output.display());
+        }
+        None => print!("{}", content.as_str()),
+    }
+
+    Ok(())
+}
+
+fn run_dedupe(args: DedupeArgs, config: &Config) -> Result<(), SealedError> {
+    if args.dry_run {
+        let vars = read_vars(&args.env_file)?;
+        let duplicates = duplicates(&vars);
+        for duplicate in &duplicates {
+            let (last, earlier) = duplicate.lines.split_last().unwrap_or((&0, &[]));
+            println!(
+                "{}: would remove {}, keeping line {}",
+                duplicate.key,
+                line_list(earlier),
+                last
+            );
+        }
+        if duplicates.is_empty() {
+            eprintln!("no duplicate assignments in {}", args.env_file.display());
+        } else {
+            eprintln!("would remove {}", count_duplicates(&duplicates));
+        }
         return Ok(());
     }
 
-    let key_input = select_key_input(args.key, args.key_file, args.key_stdin)?;
-    let key = match key_input {
-        Some(input) => read_key(input)?,
-        None => {
-            return Err(SealedError::Crypto(
-                "encrypted value requires a key; provide --key, --key-file, --key-stdin, or set SEALED_KEY".to_string(),
-            ));
+    let keyring = if mac_required(&args.env_file, config)? {
+        read_keyring(select_keyring_input(args.key_args, config)?)?
+    } else {
+        Keyring::new()
+    };
+    let mac = mac_update(&args.env_file, config, &keyring)?;
+
+    let duplicates = dedupe_vars(&args.env_file, mac)?;
+    for duplicate in &duplicates {
+        let (last, earlier) = duplicate.lines.split_last().unwrap_or((&0, &[]));
+        println!(
+            "{}: removed {}, kept line {}",
+            duplicate.key,
+            line_list(earlier),
+            last
+        );
+    }
+    if duplicates.is_empty() {
+        eprintln!("no duplicate assignments in {}", args.env_file.display());
+    } else {
+        eprintln!("removed {}", count_duplicates(&duplicates));
+    }
+
+    Ok(())
+}
+
+/// Warn on stderr about variables assigned more than once, or only about `only`.
+fn warn_duplicates(env_file: &Path, vars: &[EnvVar], only: Option<&str>) {
+    for duplicate in duplicates(vars) {
+        if only.is_some_and(|only| only != duplicate.key) {
+            continue;
         }
+
+        eprintln!(
+            "warning: {}: {} is assigned {} times ({}); the last one takes effect (run `sealed dedupe` to remove the others)",
+            env_file.display(),
+            duplicate.key,
+            duplicate.lines.len(),
+            line_list(&duplicate.lines)
+        );
+    }
+}
+
+fn count_duplicates(duplicates: &[Duplicate]) -> String {
+    match duplicates.iter().map(|d| d.lines.len() - 1).sum() {
+        1 => "1 duplicate assignment".to_string(),
+        n => format!("{} duplicate assignments", n),
+    }
+}
+
+// "line 3" or "lines 3, 7".
+fn line_list(lines: &[usize]) -> String {
+    let numbers: Vec<String> = lines.iter().map(usize::to_string).collect();
+    match numbers.len() {
+        1 => format!("line {}", numbers[0]),
+        _ => format!("lines {}", numbers.join(", ")),
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn run_get(args: GetArgs, config: &Config) -> Result<(), SealedError> {
+    let encrypted = read_vars(&args.env_file)?
+        .into_iter()
+        .rfind(|var| var.key == args.var_name)
+        .is_some_and(|var| is_encrypted(&var.value));
+    let keyring = if encrypted || mac_required(&args.env_file, config)? {
+        let keyring_input = select_keyring_input(args.key_args, config)?;
+        if keyring_input.is_empty() {
+            return Err(if encrypted {
+                SealedError::Crypto(format!(
+                    "encrypted value requires a key; {}",
+                    provide_key_hint(KeySources::KeysAndIdentity)
+                ))
+            } else {
+                mac_key_required()
+            });
+        }
+        read_keyring(keyring_input)?
+    } else {
+        Keyring::new()
     };
 
-    let decrypted = decrypt_value(&key, &args.var_name, &value)?;
+    let vars = read_verified_vars(&args.env_file, &keyring, config.mac)?;
+    warn_duplicates(&args.env_file, &vars, Some(&args.var_name));
+
+    let value = vars
+        .into_iter()
+        .rfind(|var| var.key == args.var_name)
+        .map(|var| var.value)
+        .ok_or_else(|| {
+            SealedError::VarNotFound(format!(
+                "variable '{}' not found in {}",
+                args.var_name,
+                args.env_file.display()
+            ))
+        })?;
+
+    if !is_encrypted(&value) {
+        if config.policy.rule(&args.var_name) == Some(Rule::Encrypted) {
+            eprintln!(
+                "warning: {} is plaintext, but the project policy requires it to be encrypted",
+                args.var_name
+            );
+        }
+        println!("{}", value);
+        return Ok(());
+    }
+
+    let context = context_for(args.context.as_deref(), config, &args.env_file);
+    let plaintext = keyring.decrypt(context, &args.var_name, &value)?;
 
     if args.reveal {
-        let plaintext = String::from_utf8(decrypted.expose_secret().to_vec())
-            .map_err(|_| SealedError::Crypto("decrypted value is not valid UTF-8".to_string()))?;
-        println!("{}", plaintext);
+        println!("{}", plaintext.expose_secret());
     } else {
         eprintln!("value is encrypted; use --reveal to print plaintext");
     }
@@ -105,27 +413,900 @@ fn run_get(args: GetArgs) -> Result<(), SealedError> {
     Ok(())
 }
 
-fn run_keygen(args: KeygenArgs) -> Result<(), SealedError> {
-    let mut key = [0u8; 32];
-    let mut rng = OsRng;
-    rng.try_fill_bytes(&mut key)
-        .map_err(|_| SealedError::Crypto("failed to generate key".to_string()))?;
-
-    let b64 = general_purpose::STANDARD.encode(key);
+fn run_keygen(args: KeygenArgs, config: &Config, config_path: &Path) -> Result<(), SealedError> {
+    if args.passphrase {
+        return run_keygen_passphrase(config, config_path);
+    }
+    if args.recipient {
+        return run_keygen_recipient(args.out_file.as_deref());
+    }
 
-    key.zeroize();
+    let key = Key::generate()?;
+    let b64 = key.to_base64();
+    eprintln!("key id: {}", key.id());
 
     if let Some(path) = args.out_file {
-        fs::write(&path, format!("{}\n", b64)).map_err(|e| {
-            SealedError::EnvFile(format!(
-                "failed to write key file {}: {}",
-                path.display(),
+        let content = Zeroizing::new(format!("{}\n", b64.expose_secret()));
+        write_private(&path, &content)?;
+    } else {
+        println!("{}", b64.expose_secret());
+    }
+
+    Ok(())
+}
+
+fn run_keygen_recipient(out_file: Option<&Path>) -> Result<(), SealedError> {
+    let identity = Identity::generate()?;
+    let recipient = identity.to_recipient();
+    let encoded = identity.to_encoded();
+    let content = Zeroizing::new(format!(
+        "# recipient: {}\n{}\n",
+        recipient,
+        encoded.expose_secret()
+    ));
+
+    if let Some(path) = out_file {
+        write_private(path, &content)?;
+    } else {
+        print!("{}", content.as_str());
+    }
+
+    eprintln!("recipient: {}", recipient);
+
+    Ok(())
+}
+
+fn run_keygen_passphrase(config: &Config, config_path: &Path) -> Result<(), SealedError> {
+    if config.passphrase.is_some() {
+        return Err(SealedError::Arg(format!(
+            "{} already has a [passphrase] table; remove it first to generate a new salt",
+            config_path.display()
+        )));
+    }
+
+    let params = PassphraseParams::generate()?;
+
+    let existing = match fs::read_to_string(config_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(SealedError::Arg(format!(
+                "failed to read config file {}: {}",
+                config_path.display(),
+                e
+            )));
+        }
+    };
+    let separator = match existing.as_str() {
+        "" => "",
+        s if s.ends_with('\n') => "\n",
+        _ => "\n\n",
+    };
+
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(config_path)
+        .and_then(|mut file| write!(file, "{}{}", separator, params.to_toml()))
+        .map_err(|e| {
+            SealedError::Arg(format!(
+                "failed to write config file {}: {}",
+                config_path.display(),
                 e
             ))
         })?;
+
+    eprintln!(
+        "wrote passphrase salt to {}; share the passphrase over a secure channel",
+        config_path.display()
+    );
+
+    Ok(())
+}
+
+fn run_run(args: RunArgs, config: &Config) -> Result<(), SealedError> {
+    let encrypted = read_vars(&args.env_file)?
+        .iter()
+        .any(|var| is_encrypted(&var.value));
+    let context = context_for(args.context.as_deref(), config, &args.env_file);
+
+    let keyring = if encrypted || mac_required(&args.env_file, config)? {
+        let keyring_input = select_keyring_input(args.key_args, config)?;
+        if keyring_input.is_empty() {
+            return Err(if encrypted {
+                SealedError::Crypto(format!(
+                    "env file contains encrypted values; {}",
+                    provide_key_hint(KeySources::KeysAndIdentity)
+                ))
+            } else {
+                mac_key_required()
+            });
+        }
+        read_keyring(keyring_input)?
+    } else {
+        Keyring::new()
+    };
+
+    // The file is read again together with its trailer, so what runs is what was verified.
+    let vars = read_verified_vars(&args.env_file, &keyring, config.mac)?;
+
+    let (program, program_args) = args
+        .command
+        .split_first()
+        .ok_or_else(|| SealedError::Arg("command required".to_string()))?;
+
+    let mut command = Command::new(program);
+    command.args(program_args);
+
+    for var in &vars {
+        if is_encrypted(&var.value) {
+            let plaintext = keyring
+                .decrypt(context, &var.key, &var.value)
+                .map_err(|e| SealedError::Crypto(format!("{}: {}", var.key, e)))?;
+            command.env(&var.key, plaintext.expose_secret());
+        } else {
+            command.env(&var.key, &var.value);
+        }
+    }
+
+    exec(command, &program.to_string_lossy())
+}
+
+#[derive(Serialize)]
+struct ListEntry<'a> {
+    key: &'a str,
+    line: usize,
+    encrypted: bool,
+    key_id: Option<String>,
+    decrypts: Option<bool>,
+}
+
+fn run_list(args: ListArgs, config: &Config) -> Result<(), SealedError> {
+    let vars = read_vars(&args.env_file)?;
+    warn_duplicates(&args.env_file, &vars, None);
+    let context = context_for(args.context.as_deref(), config, &args.env_file);
+    let keyring_input = select_keyring_input(args.key_args, config)?;
+    // Only encrypted values are decrypted, so a plaintext file never runs a key command.
+    let keyring = if keyring_input.is_empty() || !vars.iter().any(|var| is_encrypted(&var.value)) {
+        None
     } else {
-        println!("{}", b64);
+        Some(read_keyring(keyring_input)?)
+    };
+
+    let entries: Vec<ListEntry> = vars
+        .iter()
+        .map(|var| {
+            let encrypted = is_encrypted(&var.value);
+            let key_id = match var.value.parse::<SealedValue>() {
+                Ok(SealedValue::Symmetric(value)) => value.key_id().map(str::to_string),
+                _ => None,
+            };
+            let decrypts = match &keyring {
+                Some(keyring) if encrypted => {
+                    Some(keyring.decrypt(context, &var.key, &var.value).is_ok())
+                }
+                _ => None,
+            };
+
+            ListEntry {
+                key: &var.key,
+                line: var.line,
+                encrypted,
+                key_id,
+                decrypts,
+            }
+        })
+        .collect();
+
+    match args.format {
+        OutputFormat::Json => {
+            let json = serde_json::to_string_pretty(&entries)
+                .map_err(|e| SealedError::EnvFile(format!("failed to serialize list: {}", e)))?;
+            println!("{}", json);
+        }
+        OutputFormat::Text => {
+            let line_width = entries.iter().map(|e| e.line.to_string().len()).max();
+            let key_width = entries.iter().map(|e| e.key.len()).max();
+
+            for entry in &entries {
+                let status = if entry.encrypted {
+                    "encrypted"
+                } else {
+                    "plain"
+                };
+                let key_id = match &entry.key_id {
+                    Some(id) => format!("  key {}", id),
+                    None => String::new(),
+                };
+                let decrypts = match entry.decrypts {
+                    Some(true) => "  decrypts",
+                    Some(false) => "  decryption failed",
+                    None => "",
+                };
+
+                println!(
+                    "{:>lw$}  {:<kw$}  {}{}{}",
+                    entry.line,
+                    entry.key,
+                    status,
+                    key_id,
+                    decrypts,
+                    lw = line_width.unwrap_or(0),
+                    kw = key_width.unwrap_or(0),
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn run_unset(args: UnsetArgs, config: &Config) -> Result<(), SealedError> {
+    let keyring = if mac_required(&args.env_file, config)? {
+        read_keyring(select_keyring_input(args.key_args, config)?)?
+    } else {
+        Keyring::new()
+    };
+    let mac = mac_update(&args.env_file, config, &keyring)?;
+
+    let removed = remove_var(&args.env_file, &args.var_name, mac)?;
+
+    if !removed && !args.ignore_missing {
+        return Err(SealedError::VarNotFound(format!(
+            "variable '{}' not found in {}",
+            args.var_name,
+            args.env_file.display()
+        )));
     }
 
     Ok(())
 }
+
+fn run_rekey(args: RekeyArgs, config: &Config) -> Result<(), SealedError> {
+    if args.key_args.uses_stdin() && args.new_key_args.new_key_stdin {
+        return Err(SealedError::Arg(
+            "stdin may be used only once; --new-key-stdin cannot be combined with --key-stdin or --passphrase-stdin"
+                .to_string(),
+        ));
+    }
+
+    let keyring_input = select_keyring_input(args.key_args, config)?;
+    if keyring_input.is_empty() {
+        return Err(SealedError::Arg(format!(
+            "key required; {}",
+            provide_key_hint(KeySources::Keys)
+        )));
+    }
+    let new_key_input = select_new_key_input(args.new_key_args)?;
+
+    let keyring = read_keyring(keyring_input)?;
+    let new_key = read_key(new_key_input)?;
+    let new_key_id = new_key.id();
+    let context = context_for(args.context.as_deref(), config, &args.env_file);
+    // The trailer is checked with the current keys and recomputed with the new one.
+    let mac = MacUpdate {
+        keyring: &keyring,
+        required: config.mac,
+        new_key: Some(&new_key),
+    };
+
+    rewrite_vars(&args.env_file, mac, |name, value| {
+        // Values sealed to recipients are not tied to the symmetric key, and values that
+        // already name the new key and context were rotated by an earlier, interrupted rekey.
+        match value.parse::<SealedValue>() {
+            Ok(SealedValue::Symmetric(value))
+                if value.key_id() != Some(&new_key_id) || value.context() != context => {}
+            _ => return Ok(None),
+        }
+
+        let plaintext = decrypt_for_rewrite(&keyring, context, name, value)?;
+
+        Ok(Some(encrypt_value(&new_key, context, name, &plaintext)?))
+    })?;
+
+    Ok(())
+}
+
+fn run_recipients(
+    args: RecipientsArgs,
+    config: &Config,
+    config_path: &Path,
+) -> Result<(), SealedError> {
+    let current = config.recipients()?;
+
+    match args.command {
+        RecipientsCommand::List => {
+            for recipient in &current {
+                println!("{}", recipient);
+            }
+            Ok(())
+        }
+        RecipientsCommand::Add(args) => {
+            let mut added = parse_recipients(&args.recipients)?;
+            let mut keys = Vec::new();
+            for path in args.recipient_key_files {
+                let key = read_key(KeyInput::File(path))?;
+                added.push(Recipient::from_key(&key));
+                keys.push(key);
+            }
+
+            let mut updated = current;
+            for recipient in added {
+                if updated.contains(&recipient) {
+                    eprintln!("{} is already a recipient", recipient);
+                } else {
+                    updated.push(recipient);
+                }
+            }
+
+            let context = context_for(args.context.as_deref(), config, &args.env_file);
+            rewrap_env_file(
+                &args.env_file,
+                context,
+                args.key_args,
+                keys,
+                config,
+                &updated,
+            )?;
+            Ok(Config::save_recipients(config_path, &updated)?)
+        }
+        RecipientsCommand::Remove(args) => {
+            let mut updated = current;
+            for recipient in parse_recipients(&args.recipients)? {
+                let len = updated.len();
+                updated.retain(|r| *r != recipient);
+                if updated.len() == len {
+                    return Err(SealedError::Arg(format!(
+                        "{} is not a recipient in {}",
+                        recipient,
+                        config_path.display()
+                    )));
+                }
+            }
+            if updated.is_empty() {
+                return Err(SealedError::Arg(
+                    "cannot remove every recipient; at least one must remain".to_string(),
+                ));
+            }
+
+            let context = context_for(args.context.as_deref(), config, &args.env_file);
+            rewrap_env_file(
+                &args.env_file,
+                context,
+                args.key_args,
+                Vec::new(),
+                config,
+                &updated,
+            )?;
+            Ok(Config::save_recipients(config_path, &updated)?)
+        }
+    }
+}
+
+fn parse_recipients(recipients: &[String]) -> Result<Vec<Recipient>, SealedError> {
+    recipients
+        .iter()
+        .map(|r| r.parse().map_err(SealedError::from))
+        .collect()
+}
+
+/// Re-encrypt every encrypted value in `env_file` to `recipients`. `extra_keys` are only
+/// used to wrap for new `key:<id>` recipients.
+fn rewrap_env_file(
+    env_file: &Path,
+    context: Option<&str>,
+    key_args: KeyArgs,
+    extra_keys: Vec<Key>,
+    config: &Config,
+    recipients: &[Recipient],
+) -> Result<(), SealedError> {
+    // Recipients can be set up before any value is stored.
+    if !env_file.exists() {
+        return Ok(());
+    }
+
+    let needs_key = mac_required(env_file, config)?
+        || read_vars(env_file)?
+            .iter()
+            .any(|var| is_encrypted(&var.value));
+    let mut keyring = if needs_key {
+        read_keyring(select_keyring_input(key_args, config)?)?
+    } else {
+        Keyring::new()
+    };
+    for key in extra_keys {
+        keyring.add_key(key);
+    }
+    let mac = mac_update(env_file, config, &keyring)?;
+
+    rewrite_vars(env_file, mac, |name, value| {
+        if !is_encrypted(value) {
+            return Ok(None);
+        }
+
+        let plaintext = decrypt_for_rewrite(&keyring, context, name, value)?;
+
+        Ok(Some(encrypt_value_to(
+            recipients, &keyring, context, name, &plaintext,
+        )?))
+    })?;
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum VerifyStatus {
+    Plain,
+    Ok,
+    Malformed,
+    DecryptionFailed,
+    NotUtf8,
+}
+
+#[derive(Serialize)]
+struct VerifyEntry<'a> {
+    key: &'a str,
+    line: usize,
+    status: VerifyStatus,
+    key_id: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum MacStatus {
+    Ok,
+    Mismatch,
+    Missing,
+    Absent,
+    Invalid,
+}
+
+#[derive(Serialize)]
+struct MacReport {
+    status: MacStatus,
+    key_id: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct VerifyReport<'a> {
+    env_file: &'a Path,
+    ok: bool,
+    mac: MacReport,
+    variables: Vec<VerifyEntry<'a>>,
+}
+
+fn run_verify(args: VerifyArgs, config: &Config) -> Result<(), SealedError> {
+    let content = read_env_file(&args.env_file)?;
+    let vars = parse_vars(&content);
+    let mac = FileMac::find(&content);
+    let context = context_for(args.context.as_deref(), config, &args.env_file);
+
+    let keyring_input = select_keyring_input(args.key_args, config)?;
+    let needs_key = args.update_mac
+        || !matches!(mac, Ok(None))
+        || vars.iter().any(|var| is_encrypted(&var.value));
+    if keyring_input.is_empty() && needs_key {
+        return Err(SealedError::Arg(format!(
+            "key required; {}",
+            provide_key_hint(KeySources::KeysAndIdentity)
+        )));
+    }
+    let keyring = if needs_key {
+        read_keyring(keyring_input)?
+    } else {
+        Keyring::new()
+    };
+
+    if args.update_mac {
+        let key = keyring.keys().first().ok_or_else(mac_key_required)?;
+        update_mac(&args.env_file, key)?;
+        eprintln!("updated sealed-mac trailer (key {})", key.id());
+        return Ok(());
+    }
+
+    let variables: Vec<VerifyEntry> = vars
+        .iter()
+        .map(|var| verify_var(&keyring, context, &var.key, &var.value, var.line))
+        .collect();
+
+    let mac = match mac {
+        Ok(Some(mac)) => {
+            let (status, error) = match mac.verify(&keyring, &content) {
+                Ok(()) => (MacStatus::Ok, None),
+                Err(err @ sealed_core::Error::MacMismatch) => {
+                    (MacStatus::Mismatch, Some(err.to_string()))
+                }
+                Err(err) => (MacStatus::Invalid, Some(err.to_string())),
+            };
+            MacReport {
+                status,
+                key_id: Some(mac.key_id().to_string()),
+                error,
+            }
+        }
+        Ok(None) if config.mac => MacReport {
+            status: MacStatus::Missing,
+            key_id: None,
+            error: Some(sealed_core::Error::MissingMac.to_string()),
+        },
+        Ok(None) => MacReport {
+            status: MacStatus::Absent,
+            key_id: None,
+            error: None,
+        },
+        Err(err) => MacReport {
+            status: MacStatus::Invalid,
+            key_id: None,
+            error: Some(err.to_string()),
+        },
+    };
+
+    let failed = variables
+        .iter()
+        .filter(|entry| entry.error.is_some())
+        .count();
+    let mac_failed = mac.error.is_some();
+    let report = VerifyReport {
+        env_file: &args.env_file,
+        ok: failed == 0 && !mac_failed,
+        mac,
+        variables,
+    };
+
+    match args.format {
+        OutputFormat::Json => {
+            let json = serde_json::to_string_pretty(&report)
+                .map_err(|e| SealedError::EnvFile(format!("failed to serialize report: {}", e)))?;
+            println!("{}", json);
+        }
+        OutputFormat::Text => print_verify_report(&report),
+    }
+
+    if report.ok {
+        return Ok(());
+    }
+
+    let mut failures = Vec::new();
+    if failed > 0 {
+        failures.push(format!(
+            "{} of {} variables",
+            failed,
+            report.variables.len()
+        ));
+    }
+    if mac_failed {
+        failures.push("the sealed-mac trailer".to_string());
+    }
+
+    Err(SealedError::Crypto(format!(
+        "{}: verification failed for {}",
+        args.env_file.display(),
+        failures.join(" and ")
+    )))
+}
+
+/// Check one variable without exposing its plaintext.
+fn verify_var<'a>(
+    keyring: &Keyring,
+    context: Option<&str>,
+    key: &'a str,
+    value: &str,
+    line: usize,
+) -> VerifyEntry<'a> {
+    let entry = |status, key_id, error| VerifyEntry {
+        key,
+        line,
+        status,
+        key_id,
+        error,
+    };
+
+    // Anything that claims to be a sealed value, including unknown versions and truncated
+    // values, must parse; only other values are plaintext.
+    if !value.starts_with("ENCv") && !value.starts_with("ENCr") {
+        return entry(VerifyStatus::Plain, None, None);
+    }
+
+    let sealed = match value.parse::<SealedValue>() {
+        Ok(sealed) => sealed,
+        Err(err) => return entry(VerifyStatus::Malformed, None, Some(err.to_string())),
+    };
+    let key_id = match &sealed {
+        SealedValue::Symmetric(value) => value.key_id().map(str::to_string),
+        SealedValue::Recipients(_) => None,
+    };
+
+    match sealed.decrypt_string(keyring, context, key) {
+        Ok(_) => entry(VerifyStatus::Ok, key_id, None),
+        Err(err @ sealed_core::Error::InvalidUtf8) => {
+            entry(VerifyStatus::NotUtf8, key_id, Some(err.to_string()))
+        }
+        Err(err) => entry(
+            VerifyStatus::DecryptionFailed,
+            key_id,
+            Some(err.to_string()),
+        ),
+    }
+}
+
+fn print_verify_report(report: &VerifyReport) {
+    let line_width = report
+        .variables
+        .iter()
+        .map(|e| e.line.to_string().len())
+        .max();
+    let key_width = report.variables.iter().map(|e| e.key.len()).max();
+
+    for entry in &report.variables {
+        let status = match entry.status {
+            VerifyStatus::Plain => "plain",
+            VerifyStatus::Ok => "ok",
+            VerifyStatus::Malformed => "malformed",
+            VerifyStatus::DecryptionFailed => "decryption failed",
+            VerifyStatus::NotUtf8 => "not UTF-8",
+        };
+        let key_id = match &entry.key_id {
+            Some(id) => format!("  key {}", id),
+            None => String::new(),
+        };
+        let error = match &entry.error {
+            Some(error) => format!("  {}", error),
+            None => String::new(),
+        };
+
+        println!(
+            "{:>lw$}  {:<kw$}  {}{}{}",
+            entry.line,
+            entry.key,
+            status,
+            key_id,
+            error,
+            lw = line_width.unwrap_or(0),
+            kw = key_width.unwrap_or(0),
+        );
+    }
+
+    let mac = &report.mac;
+    let status = match mac.status {
+        MacStatus::Ok => "ok",
+        MacStatus::Mismatch => "mismatch",
+        MacStatus::Missing => "missing",
+        MacStatus::Absent => "none",
+        MacStatus::Invalid => "invalid",
+    };
+    let error = match &mac.error {
+        Some(error) => format!("  {}", error),
+        None => String::new(),
+    };
+    let key_id = match &mac.key_id {
+        Some(id) => format!("  key {}", id),
+        None => String::new(),
+    };
+    println!("sealed-mac  {}{}{}", status, key_id, error);
+}
+
+#[derive(Serialize)]
+struct CheckEntry<'a> {
+    key: &'a str,
+    line: usize,
+    required: Rule,
+}
+
+fn run_check(args: CheckArgs, config: &Config) -> Result<(), SealedError> {
+    if config.policy.is_empty() {
+        return Err(SealedError::Arg(
+            "no policy to check; add a [policy] table with `encrypted` and `plain` lists to the project config".to_string(),
+        ));
+    }
+
+    let vars = read_vars(&args.env_file)?;
+    let violations: Vec<CheckEntry> = vars
+        .iter()
+        .filter_map(|var| {
+            Some(CheckEntry {
+                key: &var.key,
+                line: var.line,
+                required: config.policy.violation(&var.key, &var.value)?,
+            })
+        })
+        .collect();
+
+    match args.format {
+        OutputFormat::Json => {
+            let json = serde_json::to_string_pretty(&violations).map_err(|e| {
+                SealedError::EnvFile(format!("failed to serialize violations: {}", e))
+            })?;
+            println!("{}", json);
+        }
+        OutputFormat::Text => {
+            let line_width = violations.iter().map(|e| e.line.to_string().len()).max();
+            let key_width = violations.iter().map(|e| e.key.len()).max();
+
+            for entry in &violations {
+                let message = match entry.required {
+                    Rule::Encrypted => "is plaintext, but must be encrypted",
+                    Rule::Plain => "is encrypted, but must stay plain",
+                };
+
+                println!(
+                    "{:>lw$}  {:<kw$}  {}",
+                    entry.line,
+                    entry.key,
+                    message,
+                    lw = line_width.unwrap_or(0),
+                    kw = key_width.unwrap_or(0),
+                );
+            }
+        }
+    }
+
+    if violations.is_empty() {
+        return Ok(());
+    }
+
+    Err(SealedError::Policy(format!(
+        "{}: {} of {} variables violate the policy",
+        args.env_file.display(),
+        violations.len(),
+        vars.len()
+    )))
+}
+
+#[derive(Serialize)]
+struct ScanEntry<'a> {
+    file: &'a Path,
+    line: usize,
+    key: String,
+    reason: String,
+}
+
+fn run_scan(args: ScanArgs, config: &Config) -> Result<(), SealedError> {
+    let allowed = |name: &str| {
+        config
+            .scan
+            .allow
+            .iter()
+            .chain(&args.allow)
+            .any(|pattern| glob_match(pattern, name))
+            || config.policy.rule(name) == Some(Rule::Plain)
+    };
+
+    let mut findings = Vec::new();
+    for env_file in &args.env_files {
+        for var in read_vars(env_file)? {
+            if allowed(&var.key) {
+                continue;
+            }
+
+            if let Some(reason) = scan::detect(&var) {
+                findings.push(ScanEntry {
+                    file: env_file,
+                    line: var.line,
+                    key: var.key,
+                    reason,
+                });
+            }
+        }
+    }
+
+    match args.format {
+        OutputFormat::Json => {
+            let json = serde_json::to_string_pretty(&findings).map_err(|e| {
+                SealedError::EnvFile(format!("failed to serialize findings: {}", e))
+            })?;
+            println!("{}", json);
+        }
+        OutputFormat::Text => {
+            let locations: Vec<String> = findings
+                .iter()
+                .map(|e| format!("{}:{}", e.file.display(), e.line))
+                .collect();
+            let location_width = locations.iter().map(String::len).max();
+            let key_width = findings.iter().map(|e| e.key.len()).max();
+
+            for (entry, location) in findings.iter().zip(&locations) {
+                println!(
+                    "{:<lw$}  {:<kw$}  {}",
+                    location,
+                    entry.key,
+                    entry.reason,
+                    lw = location_width.unwrap_or(0),
+                    kw = key_width.unwrap_or(0),
+                );
+            }
+        }
+    }
+
+    if findings.is_empty() {
+        return Ok(());
+    }
+
+    Err(SealedError::Policy(format!(
+        "found {} plaintext value{} that look like credentials; encrypt them with `sealed set` or allow them in [scan] of the project config",
+        findings.len(),
+        if findings.len() == 1 { "" } else { "s" }
+    )))
+}
+
+fn count_vars(count: usize) -> String {
+    match count {
+        1 => "1 variable".to_string(),
+        n => format!("{} variables", n),
+    }
+}
+
+/// Whether `env_file` has a `# sealed-mac:` trailer to maintain, or the project config
+/// requires one.
+fn mac_required(env_file: &Path, config: &Config) -> Result<bool, SealedError> {
+    Ok(config.mac || read_mac(env_file)?.is_some())
+}
+
+/// How a modification of `env_file` maintains its `# sealed-mac:` trailer: it is checked with
+/// `keyring` and recomputed with the key that computed it, or, if the file has none but the
+/// project config requires one, with the first key.
+fn mac_update<'a>(
+    env_file: &Path,
+    config: &Config,
+    keyring: &'a Keyring,
+) -> Result<MacUpdate<'a>, SealedError> {
+    if keyring.keys().is_empty() && mac_required(env_file, config)? {
+        return Err(mac_key_required());
+    }
+
+    Ok(MacUpdate {
+        keyring,
+        required: config.mac,
+        new_key: None,
+    })
+}
+
+/// The context for `env_file`: `--context` if given, otherwise the project config's
+/// `[contexts]` entry for the file.
+fn context_for<'a>(
+    context: Option<&'a str>,
+    config: &'a Config,
+    env_file: &Path,
+) -> Option<&'a str> {
+    context.or_else(|| config.context_for(env_file))
+}
+
+/// Decrypt a value that is about to be re-encrypted. Values not bound to any context yet are
+/// accepted, so that rewriting them binds them to `context`.
+fn decrypt_for_rewrite(
+    keyring: &Keyring,
+    context: Option<&str>,
+    name: &str,
+    value: &str,
+) -> Result<SecretString, SealedError> {
+    value
+        .parse::<SealedValue>()
+        .and_then(|sealed| {
+            let context = sealed.context().and(context);
+            sealed.decrypt_string(keyring, context, name)
+        })
+        .map_err(|e| SealedError::Crypto(format!("{}: {}", name, e)))
+}
+
+#[cfg(unix)]
+fn exec(mut command: Command, program: &str) -> Result<(), SealedError> {
+    use std::os::unix::process::CommandExt;
+
+    let err = command.exec();
+
+    Err(SealedError::Exec(format!(
+        "failed to run {}: {}",
+        program, err
+    )))
+}
+
+#[cfg(not(unix))]
+fn exec(mut command: Command, program: &str) -> Result<(), SealedError> {
+    let status = command
+        .status()
+        .map_err(|e| SealedError::Exec(format!("failed to run {}: {}", program, e)))?;
+
+    std::process::exit(status.code().unwrap_or(1));
+}
@@ -0,0 +1,183 @@
+//! Project configuration stored in a committed `.sealed.toml`.
+//!
+//! ```toml
+//! # Command whose stdout is the base64 key, used when no other key source is given.
+//! key_command = "pass show project/sealed"
+//!
+//! # Recipients that new values are encrypted to: X25519 public keys (see
+//! # `sealed keygen --recipient`) or symmetric keys by id (`key:<id>`, see `sealed keygen`).
+//! recipients = ["sealed-recipient-...", "key:1f2e3d4c"]
+//!
+//! # Require a `# sealed-mac:` trailer authenticating the whole env file (see `sealed verify`).
+//! mac = true
+//!
+//! # Salt and Argon2id costs for passphrase-derived keys (see `sealed keygen --passphrase`).
+//! [passphrase]
+//! salt = "kqfVQ0mCDlmHn7W2yHuXcg=="
+//!
+//! # Variables that must be encrypted or must stay plain (see `sealed check`).
+//! [policy]
+//! encrypted = ["*_SECRET", "*_PASSWORD", "DATABASE_URL"]
+//! plain = ["LOG_LEVEL"]
+//!
+//! # Variables that `sealed scan` never reports, by name or glob pattern.
+//! [scan]
+//! allow = ["PUBLIC_*", "SENTRY_DSN"]
+//!
+//! # Context (environment name) per env file. Values are bound to their file's context, so
+//! # a ciphertext copied from one environment into another fails to decrypt.
+//! [contexts]
+//! ".env.production" = "production"
+//! ".env.staging" = "staging"
+//! ```
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use toml_edit::{Array, DocumentMut, Item, Value};
+
+use crate::error::Error;
+use crate::passphrase::PassphraseParams;
+use crate::policy::Policy;
+use crate::recipient::Recipient;
+
+/// Default config file name, looked up in the current directory.
+pub const CONFIG_FILE: &str = ".sealed.toml";
+
+/// Parsed `.sealed.toml`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Shell command that prints the base64 key on stdout.
+    pub key_command: Option<String>,
+    /// Parameters for deriving the key from a passphrase.
+    pub passphrase: Option<PassphraseParams>,
+    /// Recipients (`sealed-recipient-...` or `key:<id>`) that `sealed set` encrypts to.
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    /// Whether env files must carry a `# sealed-mac:` trailer.
+    #[serde(default)]
+    pub mac: bool,
+    /// Context names keyed by env file path, relative to the working directory.
+    #[serde(default)]
+    pub contexts: BTreeMap<String, String>,
+    /// Variables that must be encrypted or must stay plain.
+    #[serde(default)]
+    pub policy: Policy,
+    /// Settings for `sealed scan`.
+    #[serde(default)]
+    pub scan: ScanConfig,
+}
+
+/// The `[scan]` table of the project config.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScanConfig {
+    /// Names or glob patterns of variables that are never reported.
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+impl Config {
+    /// Parse a config from TOML text.
+    pub fn from_toml(text: &str) -> Result<Self, Error> {
+        toml::from_str(text).map_err(|e| Error::Config(e.to_string()))
+    }
+
+    /// Read and parse the config file at `path`.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let text = fs::read_to_string(path).map_err(|e| {
+            Error::Config(format!(
+                "failed to read config file {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+        Self::from_toml(&text)
+            .map_err(|e| Error::Config(format!("invalid config file {}: {}", path.display(), e)))
+    }
+
+    /// The context configured for `env_file`, if any. Paths are compared without `./`
+    /// components, so `.env.production` and `./.env.production` match.
+    pub fn context_for(&self, env_file: &Path) -> Option<&str> {
+        let env_file = normalize(env_file);
+
+        self.contexts
+            .iter()
+            .find(|(path, _)| normalize(Path::new(path)) == env_file)
+            .map(|(_, context)| context.as_str())
+    }
+
+    /// Parse the configured recipients.
+    pub fn recipients(&self) -> Result<Vec<Recipient>, Error> {
+        self.recipients.iter().map(|r| r.parse()).collect()
+    }
+
+    /// Replace the `recipients` list in the config file at `path`, keeping everything else
+    /// (comments, formatting, other settings) as it is. The file is created if missing.
+    pub fn save_recipients(path: &Path, recipients: &[Recipient]) -> Result<(), Error> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(Error::Config(format!(
+                    "failed to read config file {}: {}",
+                    path.display(),
+                    e
+                )));
+            }
+        };
+
+        let mut doc = text
+            .parse::<DocumentMut>()
+            .map_err(|e| Error::Config(format!("invalid config file {}: {}", path.display(), e)))?;
+
+        let mut array = Array::new();
+        for recipient in recipients {
+            array.push_formatted(Value::from(recipient.to_string()).decorated("\n    ", ""));
+        }
+        if !array.is_empty() {
+            array.set_trailing("\n");
+            array.set_trailing_comma(true);
+        }
+
+        match doc
+            .get_mut("recipients")
+            .and_then(|item| item.as_value_mut())
+        {
+            Some(value) => {
+                let decor = value.decor().clone();
+                *value = Value::Array(array);
+                *value.decor_mut() = decor;
+            }
+            None => {
+                doc.insert("recipients", Item::Value(Value::Array(array)));
+            }
+        }
+
+        fs::write(path, doc.to_string()).map_err(|e| {
+            Error::Config(format!(
+                "failed to write config file {}: {}",
+                path.display(),
+                e
+            ))
+        })
+    }
+
+    /// Like [`Config::load`], but returns the default config if the file does not exist.
+    pub fn load_or_default(path: &Path) -> Result<Self, Error> {
+        match fs::metadata(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            _ => Self::load(path),
+        }
+    }
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
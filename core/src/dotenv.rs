@@ -0,0 +1,788 @@
+//! `.env` parsing shared by the CLI and the library.
+//!
+//! Each line of the form `[export ]KEY=VALUE` defines a variable; blank lines and lines
+//! starting with `#` are ignored. The value is one of:
+//!
+//! - unquoted: the rest of the line up to an inline comment (a `#` after whitespace), with
+//!   surrounding whitespace trimmed;
+//! - single-quoted (`'...'`): taken literally, and may span several lines;
+//! - double-quoted (`"..."`): `\n`, `\r`, `\t`, `\"`, `\\` and `\$` escapes are expanded, and
+//!   it may span several lines.
+//!
+//! Anything after a closing quote on the same line (usually a `# comment`) is ignored. A quote
+//! that is never closed is read as part of an unquoted value. Lines may end in `\n` or `\r\n`,
+//! and a leading byte order mark is ignored.
+//!
+//! [`Document`] keeps the exact text of the file, so that editing a value leaves every other
+//! byte, including line endings, untouched.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+const BOM: char = '\u{feff}';
+
+/// A parsed env file that writes back exactly as it was read, apart from the edits made to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Document {
+    bom: bool,
+    line_ending: &'static str,
+    nodes: Vec<Node>,
+}
+
+/// A line of an env file, or several lines for an assignment whose quoted value spans them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node {
+    /// 1-based line number of the first line in the parsed text.
+    pub line: usize,
+    /// The text as written, without the final line ending.
+    pub text: String,
+    /// `"\n"`, `"\r\n"`, or `""` for a last line without one.
+    pub line_ending: &'static str,
+    /// The variable assignment, or `None` for blank lines, comments and unparsable lines.
+    pub assignment: Option<Assignment>,
+}
+
+/// A variable assignment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Assignment {
+    /// Whether the line starts with `export `.
+    pub export_prefix: bool,
+    /// The variable name.
+    pub key: String,
+    /// The value with quotes removed and escapes expanded.
+    pub value: String,
+    /// Byte range of the value in the node text, including quotes but not an inline comment.
+    pub value_span: Range<usize>,
+}
+
+/// A variable together with its 1-based line number.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnvVar {
+    /// 1-based line number in the file.
+    pub line: usize,
+    /// The variable name.
+    pub key: String,
+    /// The value with quotes removed and escapes expanded.
+    pub value: String,
+}
+
+/// A variable assigned more than once.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Duplicate {
+    /// The variable name.
+    pub key: String,
+    /// 1-based line numbers of every assignment, in file order. The last one takes effect.
+    pub lines: Vec<usize>,
+}
+
+/// Parse every variable in `content`, in file order.
+///
+/// Duplicate keys are kept; later occurrences take precedence when loading.
+pub fn parse_vars(content: &str) -> Vec<EnvVar> {
+    Document::parse(content).vars()
+}
+
+/// The variables assigned more than once in `vars`, in order of their first assignment.
+pub fn duplicates(vars: &[EnvVar]) -> Vec<Duplicate> {
+    let mut lines: HashMap<&str, Vec<usize>> = HashMap::new();
+    for var in vars {
+        lines.entry(&var.key).or_default().push(var.line);
+    }
+
+    let mut duplicates = Vec::new();
+    for var in vars {
+        if let Some(lines) = lines.remove(var.key.as_str())
+            && lines.len() > 1
+        {
+            duplicates.push(Duplicate {
+                key: var.key.clone(),
+                lines,
+            });
+        }
+    }
+
+    duplicates
+}
+
+impl Document {
+    /// Parse `content`. Parsing never fails: lines that are not assignments are kept as-is.
+    pub fn parse(content: &str) -> Self {
+        let (bom, content) = match content.strip_prefix(BOM) {
+            Some(rest) => (true, rest),
+            None => (false, content),
+        };
+
+        let mut nodes = Vec::new();
+        let mut pos = 0;
+        let mut line = 1;
+
+        while pos < content.len() {
+            let node = parse_node(content, pos, line);
+            pos += node.text.len() + node.line_ending.len();
+            line = next_line(&node);
+            nodes.push(node);
+        }
+
+        let line_ending = nodes
+            .iter()
+            .map(|node| node.line_ending)
+            .find(|ending| !ending.is_empty())
+            .unwrap_or("\n");
+
+        Document {
+            bom,
+            line_ending,
+            nodes,
+        }
+    }
+
+    /// The lines of the file, in order.
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// Every variable, in file order.
+    pub fn vars(&self) -> Vec<EnvVar> {
+        self.nodes
+            .iter()
+            .filter_map(|node| {
+                node.assignment.as_ref().map(|assignment| EnvVar {
+                    line: node.line,
+                    key: assignment.key.clone(),
+                    value: assignment.value.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// The value of the last assignment to `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.nodes
+            .iter()
+            .rev()
+            .filter_map(|node| node.assignment.as_ref())
+            .find(|assignment| assignment.key == key)
+            .map(|assignment| assignment.value.as_str())
+    }
+
+    /// The line ending new lines are written with: the first one in the parsed text, or `\n`.
+    pub fn line_ending(&self) -> &'static str {
+        self.line_ending
+    }
+
+    /// Set every assignment to `key` to `value`, or append one if there is none.
+    pub fn set(&mut self, key: &str, value: &str) {
+        let mut found = false;
+
+        for node in &mut self.nodes {
+            if node.assignment.as_ref().is_some_and(|a| a.key == key) {
+                node.set_value(value);
+                found = true;
+            }
+        }
+
+        if !found {
+            self.push(&format!("{}={}", key, quote(value)));
+        }
+    }
+
+    /// Remove every assignment to `key`, returning whether there was one.
+    pub fn remove(&mut self, key: &str) -> bool {
+        let len = self.nodes.len();
+        self.retain(|node| node.assignment.as_ref().is_none_or(|a| a.key != key));
+
+        self.nodes.len() != len
+    }
+
+    /// Remove every assignment that a later one to the same key overrides, returning the
+    /// duplicates that were collapsed. The value that takes effect does not change.
+    pub fn dedupe(&mut self) -> Vec<Duplicate> {
+        let duplicates = duplicates(&self.vars());
+
+        let mut remaining: HashMap<String, usize> = duplicates
+            .iter()
+            .map(|duplicate| (duplicate.key.clone(), duplicate.lines.len()))
+            .collect();
+        self.retain(|node| {
+            let Some(count) = node
+                .assignment
+                .as_ref()
+                .and_then(|assignment| remaining.get_mut(&assignment.key))
+            else {
+                return true;
+            };
+            *count -= 1;
+            *count == 0
+        });
+
+        duplicates
+    }
+
+    /// Replace values with the result of `rewrite(key, value)` where it returns `Some`,
+    /// returning the number of replaced values.
+    pub fn rewrite<F, E>(&mut self, mut rewrite: F) -> Result<usize, E>
+    where
+        F: FnMut(&str, &str) -> Result<Option<String>, E>,
+    {
+        let mut rewritten = 0;
+
+        for node in &mut self.nodes {
+            let Some(assignment) = &node.assignment else {
+                continue;
+            };
+            if let Some(value) = rewrite(&assignment.key, &assignment.value)? {
+                node.set_value(&value);
+                rewritten += 1;
+            }
+        }
+
+        Ok(rewritten)
+    }
+
+    /// Keep only the nodes for which `keep` returns `true`.
+    ///
+    /// If the file did not end with a line ending, it still does not.
+    pub fn retain<F>(&mut self, keep: F)
+    where
+        F: FnMut(&Node) -> bool,
+    {
+        let final_line_ending = self.ends_with_line_ending();
+        self.nodes.retain(keep);
+
+        if !final_line_ending && let Some(last) = self.nodes.last_mut() {
+            last.line_ending = "";
+        }
+    }
+
+    /// Append a single line of text.
+    ///
+    /// If the file did not end with a line ending, the new line does not either.
+    pub fn push(&mut self, text: &str) {
+        let line_ending = self.line_ending;
+        let final_line_ending = self.ends_with_line_ending();
+        let line = self.nodes.last().map_or(1, next_line);
+
+        if let Some(last) = self.nodes.last_mut() {
+            last.line_ending = line_ending;
+        }
+
+        let mut node = parse_node(text, 0, line);
+        node.line_ending = if final_line_ending { line_ending } else { "" };
+        self.nodes.push(node);
+    }
+
+    fn ends_with_line_ending(&self) -> bool {
+        self.nodes
+            .last()
+            .is_none_or(|node| !node.line_ending.is_empty())
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.bom {
+            write!(f, "{}", BOM)?;
+        }
+        for node in &self.nodes {
+            write!(f, "{}{}", node.text, node.line_ending)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Node {
+    // Replace the value as written, quoting it if needed, and keep the rest of the text.
+    fn set_value(&mut self, value: &str) {
+        let Some(assignment) = &mut self.assignment else {
+            return;
+        };
+
+        let quoted = quote(value);
+        // A comment right after the old value, as in `KEY= # comment`, would otherwise become
+        // part of the new one.
+        let separator = if self.text[assignment.value_span.end..].starts_with('#') {
+            " "
+        } else {
+            ""
+        };
+        self.text.replace_range(
+            assignment.value_span.clone(),
+            &format!("{}{}", quoted, separator),
+        );
+        assignment.value_span.end = assignment.value_span.start + quoted.len();
+        assignment.value = value.to_string();
+    }
+}
+
+/// Quote `value` so that [`Document::parse`] (and other dotenv loaders) read it back unchanged.
+///
+/// Values made only of letters, digits and `_-.,:/+=@%^~` are written as-is, which covers
+/// every sealed value. Others are single-quoted, or double-quoted with escapes if they contain
+/// a single quote or a line break. `$` is escaped in double quotes, so that loaders that
+/// expand variables, such as docker compose, read it literally.
+pub fn quote(value: &str) -> Cow<'_, str> {
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "_-.,:/+=@%^~".contains(c);
+
+    if value.chars().all(is_plain) {
+        return Cow::Borrowed(value);
+    }
+
+    if !value.contains(['\'', '\n', '\r']) {
+        return Cow::Owned(format!("'{}'", value));
+    }
+
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '$' => quoted.push_str("\\$"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+
+    Cow::Owned(quoted)
+}
+
+// The node starting at `start`, which is the start of a line.
+fn parse_node(content: &str, start: usize, line: usize) -> Node {
+    let (end, assignment) = match parse_assignment(content, start) {
+        Some((end, assignment)) => (end, Some(assignment)),
+        None => (line_content_end(content, start), None),
+    };
+
+    let line_ending = if content[end..].starts_with("\r\n") {
+        "\r\n"
+    } else if content[end..].starts_with('\n') {
+        "\n"
+    } else {
+        ""
+    };
+
+    Node {
+        line,
+        text: content[start..end].to_string(),
+        line_ending,
+        assignment,
+    }
+}
+
+// 1-based line number of the line after `node`.
+fn next_line(node: &Node) -> usize {
+    node.line + node.text.matches('\n').count() + 1
+}
+
+// The assignment starting at `start` and the end of its last line, without the line ending.
+// Value spans are relative to `start`.
+fn parse_assignment(content: &str, start: usize) -> Option<(usize, Assignment)> {
+    let end = line_content_end(content, start);
+    let text = &content[start..end];
+
+    let trimmed = text.trim_start();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return None;
+    }
+
+    let (export_prefix, rest) = match trimmed.strip_prefix("export ") {
+        Some(stripped) => (true, stripped),
+        None => (false, trimmed),
+    };
+
+    let eq = rest.find('=')?;
+    let key = rest[..eq].trim_end();
+    if key.is_empty() {
+        return None;
+    }
+
+    let after_eq = end - rest.len() + eq + 1;
+    let value_start = end - content[after_eq..end].trim_start().len();
+
+    if let Some((value, value_end)) = parse_quoted(content, value_start) {
+        let assignment = Assignment {
+            export_prefix,
+            key: key.to_string(),
+            value,
+            value_span: value_start - start..value_end - start,
+        };
+        return Some((line_content_end(content, value_end), assignment));
+    }
+
+    let value = strip_comment(&content[value_start..end], value_start > after_eq).trim_end();
+    let assignment = Assignment {
+        export_prefix,
+        key: key.to_string(),
+        value: value.to_string(),
+        value_span: value_start - start..value_start - start + value.len(),
+    };
+
+    Some((end, assignment))
+}
+
+// A quoted value starting at `start`, and the byte offset just past its closing quote.
+fn parse_quoted(content: &str, start: usize) -> Option<(String, usize)> {
+    let quote = content[start..]
+        .chars()
+        .next()
+        .filter(|c| *c == '\'' || *c == '"')?;
+    let body = start + 1;
+
+    if quote == '\'' {
+        let close = body + content[body..].find('\'')?;
+        return Some((content[body..close].to_string(), close + 1));
+    }
+
+    let mut value = String::new();
+    let mut chars = content[body..].char_indices();
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, body + offset + 1)),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 'r')) => value.push('\r'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, c @ ('"' | '\\' | '$'))) => value.push(c),
+                Some((_, c)) => {
+                    value.push('\\');
+                    value.push(c);
+                }
+                None => return None,
+            },
+            c => value.push(c),
+        }
+    }
+
+    None
+}
+
+// An inline comment starts at a `#` after whitespace. `ws_before` tells whether whitespace
+// separates the value from the `=`, so that `KEY=#value` keeps its `#`.
+fn strip_comment(raw: &str, ws_before: bool) -> &str {
+    let mut prev_ws = ws_before;
+    for (i, c) in raw.char_indices() {
+        if c == '#' && prev_ws {
+            return &raw[..i];
+        }
+        prev_ws = c.is_whitespace();
+    }
+
+    raw
+}
+
+// End of the line containing `pos`, without its line ending.
+fn line_content_end(content: &str, pos: usize) -> usize {
+    match content[pos..].find('\n') {
+        Some(i) if content[..pos + i].ends_with('\r') => pos + i - 1,
+        Some(i) => pos + i,
+        None => content.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(line: usize, key: &str, value: &str) -> EnvVar {
+        EnvVar {
+            line,
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn edited(content: &str, edit: impl FnOnce(&mut Document)) -> String {
+        let mut document = Document::parse(content);
+        edit(&mut document);
+        document.to_string()
+    }
+
+    #[test]
+    fn round_trips_line_endings_bom_and_missing_final_newline() {
+        for content in [
+            "",
+            "A=1\n",
+            "A=1\r\nB=2\r\n",
+            "A=1\nB=2",
+            "A=1\r\n# comment\r\n\r\nB='x\r\ny'",
+            "\u{feff}A=1\nB=2\n",
+            "\u{feff}A=1",
+            "A=1\r\nB=2\n",
+            "garbage\nexport A = 'x' # note\n",
+        ] {
+            assert_eq!(Document::parse(content).to_string(), content);
+        }
+    }
+
+    #[test]
+    fn parses_crlf_and_bom() {
+        assert_eq!(
+            parse_vars("\u{feff}A=1\r\nB='two'\r\nC=\"3\"\r\n"),
+            vec![var(1, "A", "1"), var(2, "B", "two"), var(3, "C", "3")]
+        );
+        // A lone `\r` is not a line ending; it is trailing whitespace of the value.
+        assert_eq!(parse_vars("A=1\r"), vec![var(1, "A", "1")]);
+        assert_eq!(Document::parse("A=1\r").to_string(), "A=1\r");
+    }
+
+    #[test]
+    fn parses_values() {
+        let content = "\
+# comment
+export A=plain # comment
+B = spaced
+C='single # not a comment'
+D=\"tab\\tnew\\nline \\$HOME \\\\ \\\" \\x\"
+E=a#b
+F=
+G=''
+H=#not-a-comment
+I= # comment
+=missing key
+not an assignment
+";
+        assert_eq!(
+            parse_vars(content),
+            vec![
+                var(2, "A", "plain"),
+                var(3, "B", "spaced"),
+                var(4, "C", "single # not a comment"),
+                var(5, "D", "tab\tnew\nline $HOME \\ \" \\x"),
+                var(6, "E", "a#b"),
+                var(7, "F", ""),
+                var(8, "G", ""),
+                var(9, "H", "#not-a-comment"),
+                var(10, "I", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn set_separates_the_value_from_a_following_comment() {
+        for content in [
+            "API_KEY= # fill me\n",
+            "API_KEY=''# fill me\n",
+            "B=#placeholder\n",
+        ] {
+            let mut document = Document::parse(content);
+            let key = document.vars()[0].key.clone();
+            document.set(&key, "ENCv2:chacha20poly1305:1b5cfc0e:bm9uY2U=:Y3Q==");
+
+            assert_eq!(
+                parse_vars(&document.to_string()),
+                vec![var(
+                    1,
+                    &key,
+                    "ENCv2:chacha20poly1305:1b5cfc0e:bm9uY2U=:Y3Q=="
+                )],
+                "{}",
+                document
+            );
+        }
+    }
+
+    #[test]
+    fn reads_unterminated_quotes_as_unquoted_values() {
+        assert_eq!(
+            parse_vars("A='open\nB=\"open\nC=2\n"),
+            vec![
+                var(1, "A", "'open"),
+                var(2, "B", "\"open"),
+                var(3, "C", "2")
+            ]
+        );
+        assert_eq!(
+            parse_vars("A=\"ends in \\"),
+            vec![var(1, "A", "\"ends in \\")]
+        );
+    }
+
+    #[test]
+    fn parses_multi_line_values() {
+        let content = "A='one\ntwo'\nB=\"three\r\nfour\" # comment\r\nC=5\n";
+        assert_eq!(
+            parse_vars(content),
+            vec![
+                var(1, "A", "one\ntwo"),
+                var(3, "B", "three\r\nfour"),
+                var(5, "C", "5"),
+            ]
+        );
+
+        let document = Document::parse(content);
+        assert_eq!(document.nodes().len(), 3);
+        assert_eq!(document.nodes()[1].text, "B=\"three\r\nfour\" # comment");
+        assert_eq!(document.nodes()[1].line_ending, "\r\n");
+    }
+
+    #[test]
+    fn quote_round_trips() {
+        for value in [
+            "",
+            "plain",
+            "ENCv2:chacha20poly1305:1b5cfc0e:abc+/=",
+            "with space",
+            " padded ",
+            "a#b",
+            "# comment",
+            "it's",
+            "say \"hi\"",
+            "both ' and \"",
+            "$HOME and ${PATH}",
+            "it's $5",
+            "back\\slash",
+            "it's back\\slash\\n",
+            "tab\there",
+            "multi\nline",
+            "crlf\r\nline",
+            "trailing\n",
+            "'",
+            "\"",
+            "\\",
+            "=",
+            "ünïcödé ✓",
+        ] {
+            let quoted = quote(value);
+            let content = format!("A={}\nB=next\n", quoted);
+            assert_eq!(
+                parse_vars(&content),
+                vec![var(1, "A", value), var(2, "B", "next")],
+                "quoted as {}",
+                quoted
+            );
+        }
+    }
+
+    #[test]
+    fn quote_leaves_sealed_values_bare() {
+        let value = "ENCv3:chacha20poly1305:1b5cfc0e:production:nonce:ct+/=";
+        assert!(matches!(quote(value), Cow::Borrowed(_)));
+        assert_eq!(quote("a b"), "'a b'");
+        assert_eq!(quote("it's $x"), "\"it's \\$x\"");
+    }
+
+    #[test]
+    fn set_keeps_untouched_bytes() {
+        let content = "\u{feff}# head\r\nexport A = 'old' # keep\r\nB=2\r\n\r\nA=old";
+        assert_eq!(
+            edited(content, |document| document.set("A", "new value")),
+            "\u{feff}# head\r\nexport A = 'new value' # keep\r\nB=2\r\n\r\nA='new value'"
+        );
+        assert_eq!(
+            edited(content, |document| document.set("C", "3")),
+            "\u{feff}# head\r\nexport A = 'old' # keep\r\nB=2\r\n\r\nA=old\r\nC=3"
+        );
+        assert_eq!(
+            edited("A=1\r\n", |document| {
+                document.set("B", "2");
+                document.set("C", "3");
+            }),
+            "A=1\r\nB=2\r\nC=3\r\n"
+        );
+        assert_eq!(edited("", |document| document.set("A", "1")), "A=1\n");
+    }
+
+    #[test]
+    fn set_and_rewrite_keep_comments_after_empty_values() {
+        let content = "A= # fill me\r\nB=''# quoted\r\nC=1";
+        let mut document = Document::parse(content);
+        document.set("A", "x");
+        document.set("A", "y");
+        assert_eq!(
+            document.to_string(),
+            "A= y # fill me\r\nB=''# quoted\r\nC=1"
+        );
+
+        let rewritten = document
+            .rewrite(|key, value| Ok::<_, ()>(value.is_empty().then(|| key.to_lowercase())))
+            .unwrap();
+        assert_eq!(rewritten, 1);
+        assert_eq!(
+            document.to_string(),
+            "A= y # fill me\r\nB=b # quoted\r\nC=1"
+        );
+        assert_eq!(
+            parse_vars(&document.to_string()),
+            vec![var(1, "A", "y"), var(2, "B", "b"), var(3, "C", "1")]
+        );
+    }
+
+    #[test]
+    fn set_replaces_multi_line_values() {
+        let content = "A='one\ntwo' # c\nB=2\n";
+        let mut document = Document::parse(content);
+        document.set("A", "x");
+        document.set("B", "3");
+        assert_eq!(document.to_string(), "A=x # c\nB=3\n");
+        assert_eq!(document.get("B"), Some("3"));
+    }
+
+    #[test]
+    fn remove_keeps_untouched_bytes() {
+        let content = "\u{feff}A=1\r\n# c\r\nB='x\r\ny'\r\nA=2\r\nC=3";
+        let mut document = Document::parse(content);
+        assert!(document.remove("A"));
+        assert!(!document.remove("missing"));
+        assert_eq!(document.to_string(), "\u{feff}# c\r\nB='x\r\ny'\r\nC=3");
+
+        assert_eq!(
+            edited("A=1\nB=2", |document| {
+                document.remove("B");
+            }),
+            "A=1"
+        );
+    }
+
+    #[test]
+    fn dedupe_keeps_the_last_assignment() {
+        let content = "A=1\r\n# c\r\nB=2\r\nA=3\r\nB=4\r\nA=5";
+        let mut document = Document::parse(content);
+        assert_eq!(
+            document.dedupe(),
+            vec![
+                Duplicate {
+                    key: "A".to_string(),
+                    lines: vec![1, 4, 6],
+                },
+                Duplicate {
+                    key: "B".to_string(),
+                    lines: vec![3, 5],
+                },
+            ]
+        );
+        assert_eq!(document.to_string(), "# c\r\nB=4\r\nA=5");
+        assert!(document.dedupe().is_empty());
+    }
+
+    #[test]
+    fn retain_keeps_missing_final_newline() {
+        let content = "A=1\nB=2\n# trailer";
+        assert_eq!(
+            edited(content, |document| document
+                .retain(|node| !node.text.starts_with('#'))),
+            "A=1\nB=2"
+        );
+        assert_eq!(
+            edited("A=1\nB=2\n", |document| document
+                .retain(|node| node.line == 1)),
+            "A=1\n"
+        );
+    }
+
+    #[test]
+    fn rewrite_replaces_selected_values() {
+        let content = "A=1 # c\r\nB='2'\r\nC=3";
+        let mut document = Document::parse(content);
+        let rewritten = document
+            .rewrite(|key, value| Ok::<_, ()>((key != "C").then(|| format!("{} {}", key, value))))
+            .unwrap();
+        assert_eq!(rewritten, 2);
+        assert_eq!(document.to_string(), "A='A 1' # c\r\nB='B 2'\r\nC=3");
+    }
+}
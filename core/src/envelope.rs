@@ -0,0 +1,502 @@
+use base64::Engine as _;
+use base64::engine::general_purpose;
+use secrecy::{ExposeSecret, SecretSlice, SecretString};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::Error;
+use crate::key::Key;
+use crate::keyring::Keyring;
+use crate::recipient::{Kind, Recipient, wrap_x25519};
+use crate::value::{
+    NONCE_LEN, aad, check_context, open, random_nonce, seal, to_utf8, validate_context,
+};
+
+const PREFIX_V1: &str = "ENCr1";
+const PREFIX_V2: &str = "ENCr2";
+const KEY_WRAP_AAD: &str = "sealed key wrap v1";
+
+/// A value encrypted to one or more recipients, in the
+/// `ENCr1:<stanzas>:<base64(nonce)>:<base64(ciphertext)>` format.
+///
+/// The plaintext is encrypted under a random data key (with the variable name as AAD), and
+/// the data key is wrapped once per recipient. Stanzas are separated by `,` and are either
+/// `x25519.<base64(ephemeral public key)>.<base64(wrapped key)>` for X25519 recipients or
+/// `key.<key id>.<base64(nonce)>.<base64(wrapped key)>` for symmetric keys.
+///
+/// A value bound to a context uses `ENCr2:<context>:<stanzas>:<base64(nonce)>:<base64(ciphertext)>`
+/// and authenticates the context together with the variable name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Envelope {
+    context: Option<String>,
+    stanzas: Vec<Stanza>,
+    nonce: [u8; NONCE_LEN],
+    ciphertext: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Stanza {
+    X25519 {
+        ephemeral: [u8; 32],
+        wrapped: Vec<u8>,
+    },
+    Key {
+        id: String,
+        nonce: [u8; NONCE_LEN],
+        wrapped: Vec<u8>,
+    },
+}
+
+impl Envelope {
+    /// Encrypt `plaintext` to every recipient in `recipients`.
+    ///
+    /// Key recipients (`key:<id>`) are wrapped with the matching key from `keyring`.
+    pub fn encrypt(
+        recipients: &[Recipient],
+        keyring: &Keyring,
+        context: Option<&str>,
+        var_name: &str,
+        plaintext: &[u8],
+    ) -> Result<Self, Error> {
+        if recipients.is_empty() {
+            return Err(Error::NoRecipients);
+        }
+        if let Some(context) = context {
+            validate_context(context)?;
+        }
+
+        let data_key = Key::generate()?;
+        let stanzas = recipients
+            .iter()
+            .map(|recipient| wrap(recipient, keyring, &data_key))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let nonce = random_nonce()?;
+        let ciphertext = seal(&data_key, &nonce, &aad(context, var_name), plaintext)?;
+
+        Ok(Envelope {
+            context: context.map(str::to_string),
+            stanzas,
+            nonce,
+            ciphertext,
+        })
+    }
+
+    /// Decrypt the value with the first key or identity in `keyring` that is a recipient,
+    /// verifying it was encrypted for `context` and `var_name`.
+    pub fn decrypt(
+        &self,
+        keyring: &Keyring,
+        context: Option<&str>,
+        var_name: &str,
+    ) -> Result<SecretSlice<u8>, Error> {
+        check_context(self.context.as_deref(), context)?;
+
+        let data_key = self.unwrap_data_key(keyring)?;
+
+        open(
+            &data_key,
+            &self.nonce,
+            &aad(self.context.as_deref(), var_name),
+            &self.ciphertext,
+        )
+    }
+
+    /// Decrypt the value and require the plaintext to be valid UTF-8.
+    pub fn decrypt_string(
+        &self,
+        keyring: &Keyring,
+        context: Option<&str>,
+        var_name: &str,
+    ) -> Result<SecretString, Error> {
+        to_utf8(self.decrypt(keyring, context, var_name)?)
+    }
+
+    /// The context the value is bound to, or `None` for `ENCr1` values.
+    pub fn context(&self) -> Option<&str> {
+        self.context.as_deref()
+    }
+
+    fn unwrap_data_key(&self, keyring: &Keyring) -> Result<Key, Error> {
+        self.stanzas
+            .iter()
+            .find_map(|stanza| match stanza {
+                Stanza::X25519 { ephemeral, wrapped } => keyring
+                    .identities()
+                    .iter()
+                    .find_map(|identity| identity.unwrap_key(ephemeral, wrapped)),
+                Stanza::Key { id, nonce, wrapped } => {
+                    let key = keyring.key_by_id(id)?;
+                    let data_key = open(key, nonce, KEY_WRAP_AAD, wrapped).ok()?;
+                    Key::from_bytes(data_key.expose_secret()).ok()
+                }
+            })
+            .ok_or(Error::NoMatchingRecipient)
+    }
+}
+
+impl FromStr for Envelope {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (context, rest) = match value.split_once(':') {
+            Some((PREFIX_V1, rest)) => (None, rest),
+            Some((PREFIX_V2, rest)) => {
+                let (context, rest) = rest.split_once(':').ok_or(Error::InvalidFormat)?;
+                validate_context(context).map_err(|_| Error::InvalidFormat)?;
+                (Some(context.to_string()), rest)
+            }
+            _ => return Err(Error::InvalidFormat),
+        };
+
+        let mut parts = rest.split(':');
+
+        let (Some(stanzas), Some(nonce_b64), Some(ct_b64), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(Error::InvalidFormat);
+        };
+
+        let stanzas = stanzas
+            .split(',')
+            .map(parse_stanza)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let nonce = general_purpose::STANDARD
+            .decode(nonce_b64)
+            .map_err(|_| Error::InvalidNonceEncoding)?
+            .try_into()
+            .map_err(|_| Error::InvalidNonceLength)?;
+
+        let ciphertext = general_purpose::STANDARD
+            .decode(ct_b64)
+            .map_err(|_| Error::InvalidCiphertextEncoding)?;
+
+        Ok(Envelope {
+            context,
+            stanzas,
+            nonce,
+            ciphertext,
+        })
+    }
+}
+
+impl fmt::Display for Envelope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.context {
+            Some(context) => write!(f, "{}:{}:", PREFIX_V2, context)?,
+            None => write!(f, "{}:", PREFIX_V1)?,
+        }
+
+        for (idx, stanza) in self.stanzas.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(",")?;
+            }
+            match stanza {
+                Stanza::X25519 { ephemeral, wrapped } => write!(
+                    f,
+                    "x25519.{}.{}",
+                    general_purpose::STANDARD.encode(ephemeral),
+                    general_purpose::STANDARD.encode(wrapped)
+                )?,
+                Stanza::Key { id, nonce, wrapped } => write!(
+                    f,
+                    "key.{}.{}.{}",
+                    id,
+                    general_purpose::STANDARD.encode(nonce),
+                    general_purpose::STANDARD.encode(wrapped)
+                )?,
+            }
+        }
+
+        write!(
+            f,
+            ":{}:{}",
+            general_purpose::STANDARD.encode(self.nonce),
+            general_purpose::STANDARD.encode(&self.ciphertext)
+        )
+    }
+}
+
+fn wrap(recipient: &Recipient, keyring: &Keyring, data_key: &Key) -> Result<Stanza, Error> {
+    match &recipient.0 {
+        Kind::X25519(public) => {
+            let (ephemeral, wrapped) = wrap_x25519(public, data_key)?;
+            Ok(Stanza::X25519 { ephemeral, wrapped })
+        }
+        Kind::Key(id) => {
+            let key = keyring
+                .key_by_id(id)
+                .ok_or_else(|| Error::MissingRecipientKey(id.clone()))?;
+            let nonce = random_nonce()?;
+            let wrapped = seal(key, &nonce, KEY_WRAP_AAD, data_key.expose_secret())?;
+
+            Ok(Stanza::Key {
+                id: id.clone(),
+                nonce,
+                wrapped,
+            })
+        }
+    }
+}
+
+fn parse_stanza(stanza: &str) -> Result<Stanza, Error> {
+    let mut parts = stanza.split('.');
+
+    match (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) {
+        (Some("x25519"), Some(ephemeral), Some(wrapped), None, None) => {
+            let ephemeral = general_purpose::STANDARD
+                .decode(ephemeral)
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or(Error::InvalidFormat)?;
+            let wrapped = general_purpose::STANDARD
+                .decode(wrapped)
+                .map_err(|_| Error::InvalidFormat)?;
+
+            Ok(Stanza::X25519 { ephemeral, wrapped })
+        }
+        (Some("key"), Some(id), Some(nonce), Some(wrapped), None) if !id.is_empty() => {
+            let nonce = general_purpose::STANDARD
+                .decode(nonce)
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or(Error::InvalidFormat)?;
+            let wrapped = general_purpose::STANDARD
+                .decode(wrapped)
+                .map_err(|_| Error::InvalidFormat)?;
+
+            Ok(Stanza::Key {
+                id: id.to_string(),
+                nonce,
+                wrapped,
+            })
+        }
+        _ => Err(Error::InvalidFormat),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recipient::Identity;
+
+    fn key(byte: u8) -> Key {
+        Key::from_bytes(&[byte; 32]).unwrap()
+    }
+
+    fn keyring(keys: &[u8], identities: Vec<Identity>) -> Keyring {
+        let mut keyring = Keyring::new();
+        for byte in keys {
+            keyring.add_key(key(*byte));
+        }
+        for identity in identities {
+            keyring.add_identity(identity);
+        }
+        keyring
+    }
+
+    fn identity(encoded: &SecretString) -> Identity {
+        encoded.expose_secret().parse().unwrap()
+    }
+
+    fn decrypt(value: &str, keyring: &Keyring, context: Option<&str>) -> Result<String, Error> {
+        let envelope: Envelope = value.parse()?;
+        envelope
+            .decrypt_string(keyring, context, "SECRET")
+            .map(|plaintext| plaintext.expose_secret().to_string())
+    }
+
+    // An envelope for two X25519 identities and a symmetric key, and the encoded identities.
+    fn encrypted(context: Option<&str>) -> (String, SecretString, SecretString) {
+        let (alice, bob) = (Identity::generate().unwrap(), Identity::generate().unwrap());
+        let recipients = [
+            alice.to_recipient(),
+            bob.to_recipient(),
+            Recipient::from_key(&key(1)),
+        ];
+        let envelope = Envelope::encrypt(
+            &recipients,
+            &keyring(&[1], Vec::new()),
+            context,
+            "SECRET",
+            b"hunter2",
+        )
+        .unwrap();
+
+        (envelope.to_string(), alice.to_encoded(), bob.to_encoded())
+    }
+
+    #[test]
+    fn every_recipient_decrypts() {
+        let (value, alice, bob) = encrypted(None);
+
+        assert!(value.starts_with("ENCr1:x25519."));
+        assert_eq!(value.parse::<Envelope>().unwrap().to_string(), value);
+        for keyring in [
+            keyring(&[], vec![identity(&alice)]),
+            keyring(&[], vec![identity(&bob)]),
+            keyring(&[1], Vec::new()),
+            keyring(&[2, 1], Vec::new()),
+        ] {
+            assert_eq!(decrypt(&value, &keyring, None).unwrap(), "hunter2");
+        }
+    }
+
+    #[test]
+    fn rejects_keys_and_identities_that_are_not_recipients() {
+        let (value, _, _) = encrypted(None);
+        let stranger = keyring(&[2], vec![Identity::generate().unwrap()]);
+
+        assert!(matches!(
+            decrypt(&value, &stranger, None),
+            Err(Error::NoMatchingRecipient)
+        ));
+        assert!(matches!(
+            Envelope::encrypt(
+                &[Recipient::from_key(&key(1))],
+                &keyring(&[2], Vec::new()),
+                None,
+                "SECRET",
+                b"x",
+            ),
+            Err(Error::MissingRecipientKey(_))
+        ));
+        assert!(matches!(
+            Envelope::encrypt(&[], &Keyring::new(), None, "SECRET", b"x"),
+            Err(Error::NoRecipients)
+        ));
+    }
+
+    #[test]
+    fn rejects_a_tampered_stanza_or_ciphertext() {
+        let (value, alice, _) = encrypted(None);
+        let envelope: Envelope = value.parse().unwrap();
+        let keyring = keyring(&[1], vec![identity(&alice)]);
+
+        let mut tampered = envelope.clone();
+        tampered.ciphertext[0] ^= 1;
+        assert!(matches!(
+            tampered.decrypt(&keyring, None, "SECRET"),
+            Err(Error::Decryption)
+        ));
+
+        // A damaged wrapped key makes that stanza unusable, leaving no recipient to decrypt.
+        let mut tampered = envelope.clone();
+        for stanza in &mut tampered.stanzas {
+            match stanza {
+                Stanza::X25519 { wrapped, .. } | Stanza::Key { wrapped, .. } => wrapped[0] ^= 1,
+            }
+        }
+        assert!(matches!(
+            tampered.decrypt(&keyring, None, "SECRET"),
+            Err(Error::NoMatchingRecipient)
+        ));
+
+        assert!(matches!(
+            envelope.decrypt(&keyring, None, "OTHER"),
+            Err(Error::Decryption)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_envelopes() {
+        let (value, _, _) = encrypted(None);
+        let (prefix, rest) = value.split_once(':').unwrap();
+        let (stanzas, tail) = rest.split_once(':').unwrap();
+
+        for malformed in [
+            format!("ENCr3:{}", rest),
+            format!("{}:{}", prefix, tail),
+            format!("{}::{}", prefix, tail),
+            format!("{}:{},:{}", prefix, stanzas, tail),
+            format!("{}:rsa.abc.def:{}", prefix, tail),
+            format!("{}:x25519.AAAA.AAAA:{}", prefix, tail),
+            format!(
+                "{}:{}.extra:{}",
+                prefix,
+                stanzas.split(',').next().unwrap(),
+                tail
+            ),
+            format!("{}:key..AAAAAAAAAAAAAAAA.AAAA:{}", prefix, tail),
+            format!("{}:key.abcd.not-base64.AAAA:{}", prefix, tail),
+            format!("{}:{}:{}:extra", prefix, stanzas, tail),
+            "ENCr2:bad context:x25519.AAAA.AAAA:AAAA:AAAA".to_string(),
+        ] {
+            assert!(
+                malformed.parse::<Envelope>().is_err(),
+                "parsed {}",
+                malformed
+            );
+        }
+        assert!(matches!(
+            format!(
+                "{}:{}:AAAA:{}",
+                prefix,
+                stanzas,
+                tail.split_once(':').unwrap().1
+            )
+            .parse::<Envelope>(),
+            Err(Error::InvalidNonceLength)
+        ));
+    }
+
+    #[test]
+    fn key_stanzas_are_bound_to_the_wrap_aad() {
+        let data_key = key(9);
+        let nonce = random_nonce().unwrap();
+        let envelope = Envelope {
+            context: None,
+            stanzas: vec![Stanza::Key {
+                id: key(1).id(),
+                nonce,
+                wrapped: seal(&key(1), &nonce, "another aad", data_key.expose_secret()).unwrap(),
+            }],
+            nonce,
+            ciphertext: seal(&data_key, &nonce, "SECRET", b"hunter2").unwrap(),
+        };
+
+        assert!(matches!(
+            envelope.decrypt(&keyring(&[1], Vec::new()), None, "SECRET"),
+            Err(Error::NoMatchingRecipient)
+        ));
+    }
+
+    #[test]
+    fn values_are_bound_to_their_context() {
+        let (value, alice, _) = encrypted(Some("production"));
+        let keyring = keyring(&[], vec![identity(&alice)]);
+
+        assert!(value.starts_with("ENCr2:production:"));
+        assert_eq!(
+            decrypt(&value, &keyring, Some("production")).unwrap(),
+            "hunter2"
+        );
+        assert!(matches!(
+            decrypt(&value, &keyring, Some("staging")),
+            Err(Error::ContextMismatch { .. })
+        ));
+        assert!(matches!(
+            decrypt(&value, &keyring, None),
+            Err(Error::ContextMismatch { .. })
+        ));
+
+        // Relabelling the value changes the AAD, so the ciphertext no longer authenticates.
+        let relabelled = value.replacen("ENCr2:production:", "ENCr2:staging:", 1);
+        assert!(matches!(
+            decrypt(&relabelled, &keyring, Some("staging")),
+            Err(Error::Decryption)
+        ));
+        let downgraded = value.replacen("ENCr2:production:", "ENCr1:", 1);
+        assert!(matches!(
+            decrypt(&downgraded, &keyring, None),
+            Err(Error::Decryption)
+        ));
+    }
+}
@@ -0,0 +1,107 @@
+use thiserror::Error;
+
+/// Errors returned by `sealed-core`.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The key is not valid base64.
+    #[error("invalid base64 key")]
+    InvalidKeyEncoding,
+    /// A key provider could not produce a key (missing variable, unreadable file, ...).
+    #[error("{0}")]
+    KeyUnavailable(String),
+    /// Deriving a key from a passphrase failed (bad salt or Argon2 parameters).
+    #[error("key derivation failed: {0}")]
+    KeyDerivation(String),
+    /// The key does not decode to 32 bytes.
+    #[error("key must be 32 bytes after base64 decode")]
+    InvalidKeyLength,
+    /// The value is not in any of the sealed value formats.
+    #[error("invalid encrypted value format")]
+    InvalidFormat,
+    /// The value names an encryption algorithm this version does not support.
+    #[error("unsupported encryption algorithm '{0}'")]
+    UnsupportedAlgorithm(String),
+    /// The identity is not a valid `sealed-identity-...` string.
+    #[error("invalid identity")]
+    InvalidIdentity,
+    /// A recipient is not a valid `sealed-recipient-...` or `key:<id>` string.
+    #[error("invalid recipient '{0}'")]
+    InvalidRecipient(String),
+    /// Encryption to recipients was requested with an empty recipient list.
+    #[error("no recipients to encrypt to")]
+    NoRecipients,
+    /// The nonce is not valid base64.
+    #[error("invalid base64 nonce")]
+    InvalidNonceEncoding,
+    /// The nonce does not decode to 12 bytes.
+    #[error("nonce must be 12 bytes after base64 decode")]
+    InvalidNonceLength,
+    /// The ciphertext is not valid base64.
+    #[error("invalid base64 ciphertext")]
+    InvalidCiphertextEncoding,
+    /// The operating system random number generator failed.
+    #[error("failed to generate random bytes")]
+    Random,
+    /// Encryption failed.
+    #[error("encryption failed")]
+    Encryption,
+    /// Authentication failed: the key, variable name or ciphertext does not match.
+    #[error("decryption failed (bad key or data)")]
+    Decryption,
+    /// The value records a key id that none of the provided keys has.
+    #[error("encrypted with key {expected}, you provided {provided}")]
+    KeyMismatch {
+        /// Id of the key that encrypted the value.
+        expected: String,
+        /// Ids of the keys that were provided, comma-separated.
+        provided: String,
+    },
+    /// A keyring file or `SEALED_KEYS` contains no keys.
+    #[error("keyring contains no keys")]
+    EmptyKeyring,
+    /// A context name contains characters other than ASCII letters, digits, `.`, `_` and `-`.
+    #[error("invalid context '{0}': use only letters, digits, '.', '_' and '-'")]
+    InvalidContext(String),
+    /// The value is bound to a different context than the one it is read in.
+    #[error("value was encrypted for context {found}, but is being read in context {expected}")]
+    ContextMismatch {
+        /// Context recorded in the value, or `(none)`.
+        found: String,
+        /// Context the value is being read in, or `(none)`.
+        expected: String,
+    },
+    /// The env file's `# sealed-mac:` trailer is malformed.
+    #[error("invalid sealed-mac trailer")]
+    InvalidMac,
+    /// The env file's variables do not match its `# sealed-mac:` trailer.
+    #[error(
+        "env file does not match its sealed-mac trailer: variables were added, removed, reordered or changed"
+    )]
+    MacMismatch,
+    /// The `# sealed-mac:` trailer was computed with a key that was not provided.
+    #[error("sealed-mac trailer was computed with key {expected}, you provided {provided}")]
+    MacKeyMismatch {
+        /// Id of the key that computed the trailer.
+        expected: String,
+        /// Ids of the keys that were provided, comma-separated.
+        provided: String,
+    },
+    /// The project config requires a `# sealed-mac:` trailer, but the env file has none.
+    #[error("env file has no sealed-mac trailer")]
+    MissingMac,
+    /// The value is encrypted with a symmetric key, but no key was provided.
+    #[error("value is encrypted with a key, but no key was provided")]
+    NoKey,
+    /// None of the provided keys or identities is a recipient of the value.
+    #[error("none of the provided keys or identities is a recipient of this value")]
+    NoMatchingRecipient,
+    /// A `key:<id>` recipient was requested, but no key with that id was provided.
+    #[error("no key with id {0} was provided to encrypt for recipient key:{0}")]
+    MissingRecipientKey(String),
+    /// The decrypted plaintext is not valid UTF-8.
+    #[error("decrypted value is not valid UTF-8")]
+    InvalidUtf8,
+    /// The project config file could not be read or parsed.
+    #[error("{0}")]
+    Config(String),
+}
@@ -0,0 +1,93 @@
+use base64::Engine as _;
+use base64::engine::general_purpose;
+use rand::TryRngCore;
+use rand::rngs::OsRng;
+use secrecy::{ExposeSecret, SecretSlice, SecretString};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::str::FromStr;
+use zeroize::Zeroize;
+
+use crate::error::Error;
+
+const KEY_ID_DOMAIN: &[u8] = b"sealed key id v1\0";
+
+/// A 32-byte ChaCha20-Poly1305 key.
+///
+/// The key bytes are zeroized on drop and never printed by `Debug`.
+pub struct Key(SecretSlice<u8>);
+
+impl Key {
+    /// Key length in bytes.
+    pub const LEN: usize = 32;
+
+    /// Generate a new random key from the operating system RNG.
+    pub fn generate() -> Result<Self, Error> {
+        let mut bytes = vec![0u8; Self::LEN];
+        OsRng
+            .try_fill_bytes(&mut bytes)
+            .map_err(|_| Error::Random)?;
+
+        Ok(Key(SecretSlice::from(bytes)))
+    }
+
+    /// Build a key from raw bytes; `bytes` must be exactly 32 bytes long.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != Self::LEN {
+            return Err(Error::InvalidKeyLength);
+        }
+
+        Ok(Key(SecretSlice::from(bytes.to_vec())))
+    }
+
+    /// Decode a base64-encoded key.
+    pub fn from_base64(b64: &str) -> Result<Self, Error> {
+        let mut decoded = general_purpose::STANDARD
+            .decode(b64)
+            .map_err(|_| Error::InvalidKeyEncoding)?;
+
+        if decoded.len() != Self::LEN {
+            decoded.zeroize();
+            return Err(Error::InvalidKeyLength);
+        }
+
+        Ok(Key(SecretSlice::from(decoded)))
+    }
+
+    /// Encode the key as base64.
+    pub fn to_base64(&self) -> SecretString {
+        SecretString::from(general_purpose::STANDARD.encode(self.expose_secret()))
+    }
+
+    /// A short fingerprint of the key: 8 hex characters, safe to commit and share.
+    ///
+    /// Derived from SHA-256 with a domain-separation prefix, so it reveals nothing useful
+    /// about the key itself.
+    pub fn id(&self) -> String {
+        let digest = Sha256::new()
+            .chain_update(KEY_ID_DOMAIN)
+            .chain_update(self.expose_secret())
+            .finalize();
+
+        digest[..4].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Access the raw key bytes.
+    pub fn expose_secret(&self) -> &[u8] {
+        self.0.expose_secret()
+    }
+}
+
+impl FromStr for Key {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Key::from_base64(s)
+    }
+}
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key([REDACTED])")
+    }
+}
@@ -0,0 +1,259 @@
+use secrecy::SecretString;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use zeroize::Zeroize;
+
+use crate::error::Error;
+use crate::key::Key;
+use crate::recipient::Identity;
+use crate::value::SealedValue;
+
+/// The keys and identities available for decryption.
+///
+/// Symmetric `ENCv3` and `ENCv2` values are decrypted with the key whose id they record,
+/// `ENCv1` values are tried against every key, and `ENCr2` and `ENCr1` values are decrypted
+/// with the first key or identity that is one of their recipients.
+///
+/// A keyring of symmetric keys can be parsed from text ([`FromStr`]) or read from a keyring
+/// file: base64 keys separated by commas, spaces or newlines, with `#` comment lines.
+#[derive(Debug, Default)]
+pub struct Keyring {
+    keys: Vec<Key>,
+    identities: Vec<Identity>,
+}
+
+impl Keyring {
+    /// An empty keyring.
+    pub fn new() -> Self {
+        Keyring::default()
+    }
+
+    /// Read a keyring file; see the type-level docs for the format.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let mut raw = fs::read_to_string(path).map_err(|e| {
+            Error::KeyUnavailable(format!(
+                "failed to read keyring file {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+        let keyring = raw.parse();
+        raw.zeroize();
+
+        keyring
+    }
+
+    /// Add a symmetric key.
+    pub fn add_key(&mut self, key: Key) {
+        self.keys.push(key);
+    }
+
+    /// Add an X25519 identity.
+    pub fn add_identity(&mut self, identity: Identity) {
+        self.identities.push(identity);
+    }
+
+    /// The symmetric keys, in insertion order.
+    pub fn keys(&self) -> &[Key] {
+        &self.keys
+    }
+
+    /// The key whose [`Key::id`] is `id`, if any.
+    pub fn key_by_id(&self, id: &str) -> Option<&Key> {
+        self.keys.iter().find(|key| key.id() == id)
+    }
+
+    /// Move every key and identity of `other` into this keyring.
+    pub fn extend(&mut self, other: Keyring) {
+        self.keys.extend(other.keys);
+        self.identities.extend(other.identities);
+    }
+
+    /// The identities, in insertion order.
+    pub fn identities(&self) -> &[Identity] {
+        &self.identities
+    }
+
+    /// Returns `true` if the keyring has no keys and no identities.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.identities.is_empty()
+    }
+
+    /// Parse and decrypt any sealed value into UTF-8 plaintext, verifying it was encrypted
+    /// for `context` and `var_name`.
+    pub fn decrypt(
+        &self,
+        context: Option<&str>,
+        var_name: &str,
+        encrypted: &str,
+    ) -> Result<SecretString, Error> {
+        encrypted
+            .parse::<SealedValue>()?
+            .decrypt_string(self, context, var_name)
+    }
+}
+
+impl FromStr for Keyring {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let keys = s
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .flat_map(|line| line.split(|c: char| c == ',' || c.is_whitespace()))
+            .filter(|entry| !entry.is_empty())
+            .map(Key::from_base64)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if keys.is_empty() {
+            return Err(Error::EmptyKeyring);
+        }
+
+        Ok(Keyring {
+            keys,
+            identities: Vec::new(),
+        })
+    }
+}
+
+impl From<Key> for Keyring {
+    fn from(key: Key) -> Self {
+        Keyring {
+            keys: vec![key],
+            identities: Vec::new(),
+        }
+    }
+}
+
+impl From<Identity> for Keyring {
+    fn from(identity: Identity) -> Self {
+        Keyring {
+            keys: Vec::new(),
+            identities: vec![identity],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::{EncryptedValue, encrypt_value};
+    use base64::Engine as _;
+    use base64::engine::general_purpose;
+    use secrecy::ExposeSecret;
+
+    fn key(byte: u8) -> Key {
+        Key::from_bytes(&[byte; 32]).unwrap()
+    }
+
+    fn encoded(byte: u8) -> String {
+        key(byte).to_base64().expose_secret().to_string()
+    }
+
+    fn ids(keyring: &Keyring) -> Vec<String> {
+        keyring.keys().iter().map(Key::id).collect()
+    }
+
+    #[test]
+    fn parses_separated_keys_and_comments() {
+        let text = format!(
+            "# current key\n{},{}\n\n  # old keys\n\t{}  {} ,\n",
+            encoded(1),
+            encoded(2),
+            encoded(3),
+            encoded(4)
+        );
+        let keyring: Keyring = text.parse().unwrap();
+
+        assert_eq!(ids(&keyring), [1, 2, 3, 4].map(|byte| key(byte).id()));
+        assert!(keyring.identities().is_empty());
+        assert_eq!(
+            keyring.key_by_id(&key(3).id()).unwrap().expose_secret(),
+            &[3; 32]
+        );
+        assert!(keyring.key_by_id(&key(5).id()).is_none());
+    }
+
+    #[test]
+    fn keeps_duplicate_keys() {
+        let keyring: Keyring = format!("{} {}", encoded(1), encoded(1)).parse().unwrap();
+
+        assert_eq!(keyring.keys().len(), 2);
+        assert_eq!(
+            keyring.key_by_id(&key(1).id()).unwrap().expose_secret(),
+            &[1; 32]
+        );
+    }
+
+    #[test]
+    fn rejects_empty_and_invalid_keyrings() {
+        for text in ["", "\n , \n", "# only a comment\n"] {
+            assert!(matches!(text.parse::<Keyring>(), Err(Error::EmptyKeyring)));
+        }
+        assert!(
+            format!("{},not-a-key", encoded(1))
+                .parse::<Keyring>()
+                .is_err()
+        );
+        // `#` only starts a comment at the beginning of a line.
+        assert!(
+            format!("{} # current", encoded(1))
+                .parse::<Keyring>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn decrypts_v2_with_the_named_key() {
+        let value = encrypt_value(&key(2), None, "SECRET", &SecretString::from("x")).unwrap();
+        let keyring: Keyring = format!("{},{}", encoded(1), encoded(2)).parse().unwrap();
+
+        assert_eq!(
+            keyring
+                .decrypt(None, "SECRET", &value)
+                .unwrap()
+                .expose_secret(),
+            "x"
+        );
+        assert!(matches!(
+            Keyring::from(key(3)).decrypt(None, "SECRET", &value),
+            Err(Error::KeyMismatch { .. })
+        ));
+        assert!(matches!(
+            Keyring::new().decrypt(None, "SECRET", &value),
+            Err(Error::NoKey)
+        ));
+    }
+
+    #[test]
+    fn tries_every_key_on_v1() {
+        let v2: EncryptedValue = encrypt_value(&key(3), None, "SECRET", &SecretString::from("x"))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let v1 = format!(
+            "ENCv1:{}:{}",
+            general_purpose::STANDARD.encode(v2.nonce()),
+            general_purpose::STANDARD.encode(v2.ciphertext())
+        );
+
+        let keyring: Keyring = format!("{} {} {}", encoded(1), encoded(2), encoded(3))
+            .parse()
+            .unwrap();
+        assert_eq!(
+            keyring
+                .decrypt(None, "SECRET", &v1)
+                .unwrap()
+                .expose_secret(),
+            "x"
+        );
+
+        let keyring: Keyring = format!("{} {}", encoded(1), encoded(2)).parse().unwrap();
+        assert!(matches!(
+            keyring.decrypt(None, "SECRET", &v1),
+            Err(Error::Decryption)
+        ));
+    }
+}
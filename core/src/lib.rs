@@ -0,0 +1,49 @@
+//! Core encryption format shared by `sealed-env` and `cargo-sealed`.
+//!
+//! This crate owns the `ENCv2:<algorithm>:<key id>:<base64(nonce)>:<base64(ciphertext)>`
+//! value format (and reading the older `ENCv1:<base64(nonce)>:<base64(ciphertext)>`), the
+//! 32-byte project [`Key`], ChaCha20-Poly1305 encryption with the variable name as AAD, and
+//! the [`dotenv`] parser.
+//!
+//! A value can be bound to a context, such as an environment name: the context is recorded in
+//! the `ENCv3` format and authenticated together with the variable name, so the ciphertext
+//! does not decrypt in another context.
+//!
+//! Values can also be encrypted to several [`Recipient`]s (the `ENCr1` [`Envelope`] format):
+//! X25519 public keys, so writers only need public keys, or symmetric keys named by id, so
+//! each team member can hold their own key. Readers decrypt with a [`Keyring`].
+//!
+//! # Example
+//! ```rust
+//! use sealed_core::{EncryptedValue, Key};
+//!
+//! let key = Key::generate()?;
+//! let value = EncryptedValue::encrypt(&key, None, "DATABASE_PASSWORD", b"supersecret")?;
+//!
+//! let encoded = value.to_string();
+//! let parsed: EncryptedValue = encoded.parse()?;
+//! let plaintext = parsed.decrypt_string(&key, None, "DATABASE_PASSWORD")?;
+//! # Ok::<(), sealed_core::Error>(())
+//! ```
+pub mod config;
+pub mod dotenv;
+pub mod mac;
+pub mod passphrase;
+pub mod policy;
+pub mod provider;
+
+mod envelope;
+mod error;
+mod key;
+mod keyring;
+mod recipient;
+mod value;
+
+pub use envelope::Envelope;
+pub use error::Error;
+pub use key::Key;
+pub use keyring::Keyring;
+pub use recipient::{Identity, Recipient};
+pub use value::{
+    EncryptedValue, SealedValue, decrypt_value, encrypt_value, encrypt_value_to, is_encrypted,
+};
@@ -0,0 +1,315 @@
+//! Whole-file integrity: the optional `# sealed-mac:` trailer.
+//!
+//! Each value is authenticated on its own, so an attacker with write access to the env file
+//! could still delete a variable, reorder lines or restore an older, valid ciphertext. The
+//! trailer is an HMAC-SHA256 over every variable name and parsed value (quotes removed and
+//! escapes expanded), each prefixed with its length, in file order. It is keyed with a key
+//! derived from the project key:
+//!
+//! ```text
+//! # sealed-mac: v1:<key id>:<base64(tag)>
+//! ```
+//!
+//! Comments, blank lines and the way a value is quoted are not covered, so they can be edited
+//! freely.
+
+use base64::Engine as _;
+use base64::engine::general_purpose;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::str::FromStr;
+use zeroize::Zeroizing;
+
+use crate::dotenv::{Document, Node, parse_vars};
+use crate::error::Error;
+use crate::key::Key;
+use crate::keyring::Keyring;
+
+/// Prefix of the trailer line.
+pub const MAC_PREFIX: &str = "# sealed-mac:";
+
+const MAC_VERSION: &str = "v1";
+const MAC_INFO: &[u8] = b"sealed file mac v1";
+const TAG_LEN: usize = 32;
+
+/// A parsed or computed `# sealed-mac:` trailer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileMac {
+    key_id: String,
+    tag: Vec<u8>,
+}
+
+impl FileMac {
+    /// Compute the MAC of the variables in `content` with `key`.
+    pub fn compute(key: &Key, content: &str) -> Self {
+        FileMac {
+            key_id: key.id(),
+            tag: mac(key, content).finalize().into_bytes().to_vec(),
+        }
+    }
+
+    /// Find the trailer in `content`; `Ok(None)` if the file has none.
+    ///
+    /// If there are several trailer lines, the last one is used.
+    pub fn find(content: &str) -> Result<Option<Self>, Error> {
+        Document::parse(content)
+            .nodes()
+            .iter()
+            .rev()
+            .find(|node| is_mac_node(node))
+            .map(|node| node.text.trim()[MAC_PREFIX.len()..].trim().parse())
+            .transpose()
+    }
+
+    /// Id of the key the MAC was computed with.
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    /// Check that the MAC matches the variables in `content`, using the keyring key it names.
+    pub fn verify(&self, keyring: &Keyring, content: &str) -> Result<(), Error> {
+        let key = keyring
+            .key_by_id(&self.key_id)
+            .ok_or_else(|| Error::MacKeyMismatch {
+                expected: self.key_id.clone(),
+                provided: keyring
+                    .keys()
+                    .iter()
+                    .map(Key::id)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            })?;
+
+        mac(key, content)
+            .verify_slice(&self.tag)
+            .map_err(|_| Error::MacMismatch)
+    }
+}
+
+impl FromStr for FileMac {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let (Some(MAC_VERSION), Some(key_id), Some(tag), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(Error::InvalidMac);
+        };
+
+        let tag = general_purpose::STANDARD
+            .decode(tag)
+            .map_err(|_| Error::InvalidMac)?;
+        if key_id.is_empty() || tag.len() != TAG_LEN {
+            return Err(Error::InvalidMac);
+        }
+
+        Ok(FileMac {
+            key_id: key_id.to_string(),
+            tag,
+        })
+    }
+}
+
+impl fmt::Display for FileMac {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            MAC_VERSION,
+            self.key_id,
+            general_purpose::STANDARD.encode(&self.tag)
+        )
+    }
+}
+
+/// Returns `true` if `line` is a `# sealed-mac:` trailer.
+pub fn is_mac_line(line: &str) -> bool {
+    line.trim().starts_with(MAC_PREFIX)
+}
+
+/// Returns `true` if `node` is a `# sealed-mac:` trailer line, rather than part of a value.
+pub fn is_mac_node(node: &Node) -> bool {
+    node.assignment.is_none() && is_mac_line(&node.text)
+}
+
+/// Replace any trailer in `content` with a fresh one computed with `key`, as the last line.
+///
+/// The rest of the file is kept byte for byte.
+pub fn with_mac(content: &str, key: &Key) -> String {
+    let mut document = Document::parse(content);
+    document.retain(|node| !is_mac_node(node));
+
+    let mac = FileMac::compute(key, &document.to_string());
+    document.push(&format!("{} {}", MAC_PREFIX, mac));
+    document.to_string()
+}
+
+// Names and values are length-prefixed so that no two different files share an input.
+fn mac(key: &Key, content: &str) -> Hmac<Sha256> {
+    let mut mac_key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(None, key.expose_secret())
+        .expand(MAC_INFO, mac_key.as_mut())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(mac_key.as_ref())
+        .expect("HMAC accepts keys of any length");
+
+    for var in parse_vars(content) {
+        for field in [&var.key, &var.value] {
+            mac.update(&(field.len() as u64).to_be_bytes());
+            mac.update(field.as_bytes());
+        }
+    }
+
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT: &str = "# comment\nA=1\nexport B='two words'\nC=ENCv2:x\n";
+
+    fn key(byte: u8) -> Key {
+        Key::from_bytes(&[byte; 32]).unwrap()
+    }
+
+    fn verify(key: &Key, content: &str) -> Result<(), Error> {
+        let mac = FileMac::find(content)?.ok_or(Error::MissingMac)?;
+        mac.verify(
+            &Keyring::from(Key::from_bytes(key.expose_secret())?),
+            content,
+        )
+    }
+
+    #[test]
+    fn verifies_a_file_with_its_trailer() {
+        let key = key(1);
+        let content = with_mac(CONTENT, &key);
+
+        assert!(content.starts_with(CONTENT));
+        assert_eq!(
+            FileMac::find(&content).unwrap(),
+            Some(FileMac::compute(&key, CONTENT))
+        );
+        assert!(verify(&key, &content).is_ok());
+    }
+
+    #[test]
+    fn ignores_comments_blank_lines_and_quoting() {
+        let key = key(1);
+        let content = with_mac(CONTENT, &key)
+            .replace("# comment\n", "\n# other\n")
+            .replace("'two words'", "\"two words\" # note");
+
+        assert!(verify(&key, &content).is_ok());
+    }
+
+    #[test]
+    fn rejects_an_edited_value() {
+        let key = key(1);
+        let content = with_mac(CONTENT, &key).replace("A=1", "A=2");
+
+        assert!(matches!(verify(&key, &content), Err(Error::MacMismatch)));
+    }
+
+    #[test]
+    fn rejects_reordered_lines() {
+        let key = key(1);
+        let content = with_mac(CONTENT, &key)
+            .replace("A=1\nexport B='two words'", "export B='two words'\nA=1");
+
+        assert!(matches!(verify(&key, &content), Err(Error::MacMismatch)));
+    }
+
+    #[test]
+    fn rejects_a_deleted_line() {
+        let key = key(1);
+        let content = with_mac(CONTENT, &key).replace("A=1\n", "");
+
+        assert!(matches!(verify(&key, &content), Err(Error::MacMismatch)));
+    }
+
+    #[test]
+    fn rejects_a_shifted_boundary_between_name_and_value() {
+        let key = key(1);
+        let content = with_mac("AB=C\n", &key).replace("AB=C", "A=BC");
+
+        assert!(matches!(verify(&key, &content), Err(Error::MacMismatch)));
+    }
+
+    #[test]
+    fn rejects_a_trailer_under_another_key() {
+        let content = with_mac(CONTENT, &key(1));
+
+        assert!(matches!(
+            verify(&key(2), &content),
+            Err(Error::MacKeyMismatch { .. })
+        ));
+
+        // A trailer that claims the right key id but was computed with another key.
+        let forged = FileMac {
+            key_id: key(2).id(),
+            tag: FileMac::compute(&key(1), CONTENT).tag,
+        };
+        let content = format!("{}{} {}\n", CONTENT, MAC_PREFIX, forged);
+        assert!(matches!(verify(&key(2), &content), Err(Error::MacMismatch)));
+    }
+
+    #[test]
+    fn uses_the_last_of_two_trailers() {
+        let (old, new) = (key(1), key(2));
+        let content = format!(
+            "{}{} {}\n",
+            with_mac(CONTENT, &old),
+            MAC_PREFIX,
+            FileMac::compute(&new, CONTENT)
+        );
+
+        assert_eq!(FileMac::find(&content).unwrap().unwrap().key_id(), new.id());
+        assert!(verify(&new, &content).is_ok());
+
+        // Recomputing leaves a single trailer.
+        let content = with_mac(&content, &old);
+        assert_eq!(content.matches(MAC_PREFIX).count(), 1);
+        assert!(verify(&old, &content).is_ok());
+    }
+
+    #[test]
+    fn finds_no_trailer_in_a_file_without_one() {
+        assert_eq!(FileMac::find(CONTENT).unwrap(), None);
+        assert!(matches!(verify(&key(1), CONTENT), Err(Error::MissingMac)));
+    }
+
+    #[test]
+    fn rejects_malformed_trailers() {
+        let tag = general_purpose::STANDARD.encode([0u8; TAG_LEN]);
+        for trailer in [
+            "v2:abcd:".to_string() + &tag,
+            "v1::".to_string() + &tag,
+            "v1:abcd:not base64".to_string(),
+            "v1:abcd:".to_string() + &general_purpose::STANDARD.encode([0u8; 16]),
+            "v1:abcd:".to_string() + &tag + ":extra",
+        ] {
+            let content = format!("{}{} {}\n", CONTENT, MAC_PREFIX, trailer);
+            assert!(
+                matches!(FileMac::find(&content), Err(Error::InvalidMac)),
+                "{}",
+                trailer
+            );
+        }
+    }
+
+    #[test]
+    fn tells_trailers_from_values() {
+        let content = format!("A='x\n{} v1:abcd:tag\n'\n", MAC_PREFIX);
+        let document = Document::parse(&content);
+
+        assert!(!document.nodes().iter().any(is_mac_node));
+        assert_eq!(FileMac::find(&content).unwrap(), None);
+        assert!(is_mac_line("  # sealed-mac: v1:abcd:tag"));
+    }
+}
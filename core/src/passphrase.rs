@@ -0,0 +1,121 @@
+//! Keys derived from a shared passphrase with Argon2id.
+//!
+//! The salt and cost parameters live in the `[passphrase]` table of the project config,
+//! so everyone who knows the passphrase derives the same key:
+//!
+//! ```toml
+//! [passphrase]
+//! salt = "kqfVQ0mCDlmHn7W2yHuXcg=="
+//! ```
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::Engine as _;
+use base64::engine::general_purpose;
+use rand::TryRngCore;
+use rand::rngs::OsRng;
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use zeroize::Zeroize;
+
+use crate::error::Error;
+use crate::key::Key;
+use crate::provider::KeyProvider;
+
+const SALT_LEN: usize = 16;
+
+/// Argon2id parameters from the `[passphrase]` config table.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PassphraseParams {
+    /// Base64-encoded per-project salt.
+    pub salt: String,
+    /// Memory cost in KiB.
+    #[serde(default = "default_memory_kib")]
+    pub memory_kib: u32,
+    /// Number of passes.
+    #[serde(default = "default_iterations")]
+    pub iterations: u32,
+    /// Degree of parallelism.
+    #[serde(default = "default_parallelism")]
+    pub parallelism: u32,
+}
+
+impl PassphraseParams {
+    /// Generate parameters with a fresh random salt and default costs.
+    pub fn generate() -> Result<Self, Error> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.try_fill_bytes(&mut salt).map_err(|_| Error::Random)?;
+
+        Ok(PassphraseParams {
+            salt: general_purpose::STANDARD.encode(salt),
+            memory_kib: default_memory_kib(),
+            iterations: default_iterations(),
+            parallelism: default_parallelism(),
+        })
+    }
+
+    /// Derive the 32-byte key for `passphrase`.
+    pub fn derive_key(&self, passphrase: &SecretString) -> Result<Key, Error> {
+        let salt = general_purpose::STANDARD
+            .decode(&self.salt)
+            .map_err(|_| Error::KeyDerivation("invalid base64 salt".to_string()))?;
+
+        let params = Params::new(
+            self.memory_kib,
+            self.iterations,
+            self.parallelism,
+            Some(Key::LEN),
+        )
+        .map_err(|e| Error::KeyDerivation(e.to_string()))?;
+
+        let mut out = [0u8; Key::LEN];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.expose_secret().as_bytes(), &salt, &mut out)
+            .map_err(|e| Error::KeyDerivation(e.to_string()))?;
+
+        let key = Key::from_bytes(&out);
+        out.zeroize();
+
+        key
+    }
+
+    /// Render the parameters as a `[passphrase]` TOML table.
+    pub fn to_toml(&self) -> String {
+        format!(
+            "[passphrase]\nsalt = \"{}\"\nmemory_kib = {}\niterations = {}\nparallelism = {}\n",
+            self.salt, self.memory_kib, self.iterations, self.parallelism
+        )
+    }
+}
+
+/// Derives the key from a passphrase and the project's [`PassphraseParams`].
+#[derive(Debug)]
+pub struct PassphraseKey {
+    passphrase: SecretString,
+    params: PassphraseParams,
+}
+
+impl PassphraseKey {
+    /// Derive the key from `passphrase` using `params`.
+    pub fn new(passphrase: SecretString, params: PassphraseParams) -> Self {
+        PassphraseKey { passphrase, params }
+    }
+}
+
+impl KeyProvider for PassphraseKey {
+    fn provide(&self) -> Result<Key, Error> {
+        self.params.derive_key(&self.passphrase)
+    }
+}
+
+fn default_memory_kib() -> u32 {
+    Params::DEFAULT_M_COST
+}
+
+fn default_iterations() -> u32 {
+    Params::DEFAULT_T_COST
+}
+
+fn default_parallelism() -> u32 {
+    Params::DEFAULT_P_COST
+}
@@ -0,0 +1,194 @@
+//! Which variables must be encrypted and which must stay plain.
+//!
+//! The policy is the `[policy]` table of `.sealed.toml`. Entries are variable names or glob
+//! patterns, where `*` matches any run of characters and `?` a single character:
+//!
+//! ```toml
+//! [policy]
+//! encrypted = ["*_SECRET", "*_PASSWORD", "DATABASE_URL"]
+//! plain = ["LOG_LEVEL", "FEATURE_*"]
+//! ```
+//!
+//! An exact name takes precedence over a pattern; if a variable matches both lists at the
+//! same level, it must be encrypted.
+
+use serde::{Deserialize, Serialize};
+
+use crate::value::is_encrypted;
+
+/// The `[policy]` table of the project config.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// Names or patterns of variables that must be encrypted.
+    #[serde(default)]
+    pub encrypted: Vec<String>,
+    /// Names or patterns of variables that must stay plain.
+    #[serde(default)]
+    pub plain: Vec<String>,
+}
+
+/// What the policy requires of a variable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Rule {
+    /// The value must be encrypted.
+    Encrypted,
+    /// The value must stay plain.
+    Plain,
+}
+
+impl Policy {
+    /// Returns `true` if the policy lists no variables.
+    pub fn is_empty(&self) -> bool {
+        self.encrypted.is_empty() && self.plain.is_empty()
+    }
+
+    /// The rule for `name`, or `None` if the policy does not mention it.
+    pub fn rule(&self, name: &str) -> Option<Rule> {
+        let exact = |entries: &[String]| entries.iter().any(|entry| entry == name);
+        let pattern = |entries: &[String]| entries.iter().any(|entry| glob_match(entry, name));
+
+        if exact(&self.encrypted) {
+            Some(Rule::Encrypted)
+        } else if exact(&self.plain) {
+            Some(Rule::Plain)
+        } else if pattern(&self.encrypted) {
+            Some(Rule::Encrypted)
+        } else if pattern(&self.plain) {
+            Some(Rule::Plain)
+        } else {
+            None
+        }
+    }
+
+    /// The rule that `name=value` breaks, if any.
+    pub fn violation(&self, name: &str, value: &str) -> Option<Rule> {
+        match self.rule(name)? {
+            Rule::Encrypted if !is_encrypted(value) => Some(Rule::Encrypted),
+            Rule::Plain if is_encrypted(value) => Some(Rule::Plain),
+            _ => None,
+        }
+    }
+}
+
+/// Match `name` against a glob `pattern` with `*` and `?` wildcards.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and the name position it was tried at, for backtracking.
+    let mut star = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(encrypted: &[&str], plain: &[&str]) -> Policy {
+        Policy {
+            encrypted: encrypted.iter().map(|entry| entry.to_string()).collect(),
+            plain: plain.iter().map(|entry| entry.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn matches_stars_anywhere() {
+        let cases = [
+            ("*_SECRET", "STRIPE_SECRET", true),
+            ("*_SECRET", "_SECRET", true),
+            ("*_SECRET", "STRIPE_SECRET_KEY", false),
+            ("FEATURE_*", "FEATURE_FLAGS", true),
+            ("FEATURE_*", "FEATURE_", true),
+            ("FEATURE_*", "FEATURES", false),
+            ("AWS_*_KEY", "AWS_SECRET_ACCESS_KEY", true),
+            ("AWS_*_KEY", "AWS__KEY", true),
+            ("AWS_*_KEY", "AWS_KEY", false),
+            ("*_KEY_*", "A_KEY_KEY_B", true),
+            ("*A*B*", "xxAyyBzz", true),
+            ("*A*B*", "xxByyAzz", false),
+        ];
+        for (pattern, name, expected) in cases {
+            assert_eq!(glob_match(pattern, name), expected, "{} {}", pattern, name);
+        }
+    }
+
+    #[test]
+    fn matches_consecutive_stars_and_question_marks() {
+        assert!(glob_match("**", "ANYTHING"));
+        assert!(glob_match("A**B", "AB"));
+        assert!(glob_match("A**B", "AxyzB"));
+        assert!(glob_match("A*?*B", "AxB"));
+        assert!(!glob_match("A*?*B", "AB"));
+        assert!(glob_match("DB_?", "DB_1"));
+        assert!(!glob_match("DB_?", "DB_12"));
+        assert!(!glob_match("DB_?", "DB_"));
+    }
+
+    #[test]
+    fn matches_empty_patterns_and_names() {
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "A"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("***", ""));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("A", ""));
+    }
+
+    #[test]
+    fn exact_names_take_precedence_over_patterns() {
+        let policy = policy(&["*_SECRET", "DATABASE_URL"], &["PUBLIC_SECRET", "*_URL"]);
+
+        assert_eq!(policy.rule("PUBLIC_SECRET"), Some(Rule::Plain));
+        assert_eq!(policy.rule("DATABASE_URL"), Some(Rule::Encrypted));
+        assert_eq!(policy.rule("STRIPE_SECRET"), Some(Rule::Encrypted));
+        assert_eq!(policy.rule("REDIS_URL"), Some(Rule::Plain));
+        assert_eq!(policy.rule("LOG_LEVEL"), None);
+    }
+
+    #[test]
+    fn encrypted_wins_at_the_same_level() {
+        let policy = policy(&["API_KEY", "*_TOKEN"], &["API_KEY", "SLACK_*"]);
+
+        assert_eq!(policy.rule("API_KEY"), Some(Rule::Encrypted));
+        assert_eq!(policy.rule("SLACK_TOKEN"), Some(Rule::Encrypted));
+        assert_eq!(policy.rule("SLACK_CHANNEL"), Some(Rule::Plain));
+    }
+
+    #[test]
+    fn reports_violations() {
+        let policy = policy(&["*_SECRET"], &["LOG_LEVEL"]);
+
+        assert_eq!(policy.violation("A_SECRET", "plain"), Some(Rule::Encrypted));
+        assert_eq!(policy.violation("A_SECRET", "ENCv2:x"), None);
+        assert_eq!(policy.violation("LOG_LEVEL", "ENCv2:x"), Some(Rule::Plain));
+        assert_eq!(policy.violation("LOG_LEVEL", "info"), None);
+        assert_eq!(policy.violation("OTHER", "ENCv2:x"), None);
+        assert!(!policy.is_empty());
+        assert!(Policy::default().is_empty());
+    }
+}
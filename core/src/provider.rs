@@ -0,0 +1,206 @@
+//! Pluggable sources for the decryption key.
+//!
+//! A [`KeyProvider`] produces a [`Key`] on demand. The built-in providers read a base64 key
+//! from an environment variable, a file, an inherited file descriptor, or the stdout of a
+//! command (for password managers and secrets agents). Implement the trait to plug in any
+//! other source.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use zeroize::Zeroize;
+
+use crate::error::Error;
+use crate::key::Key;
+
+/// A source of the decryption key.
+///
+/// # Examples
+/// ```rust
+/// use sealed_core::provider::KeyProvider;
+/// use sealed_core::{Error, Key};
+///
+/// struct Vault;
+///
+/// impl KeyProvider for Vault {
+///     fn provide(&self) -> Result<Key, Error> {
+///         let b64 = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="; // fetch from your vault
+///         Key::from_base64(b64)
+///     }
+/// }
+///
+/// let key = Vault.provide()?;
+/// # Ok::<(), Error>(())
+/// ```
+pub trait KeyProvider {
+    /// Produce the key, or [`Error::KeyUnavailable`] if the source has no key.
+    fn provide(&self) -> Result<Key, Error>;
+}
+
+impl<P: KeyProvider + ?Sized> KeyProvider for &P {
+    fn provide(&self) -> Result<Key, Error> {
+        (**self).provide()
+    }
+}
+
+impl<P: KeyProvider + ?Sized> KeyProvider for Box<P> {
+    fn provide(&self) -> Result<Key, Error> {
+        (**self).provide()
+    }
+}
+
+/// Reads a base64 key from an environment variable (`SEALED_KEY` by default).
+#[derive(Clone, Debug)]
+pub struct EnvKey {
+    var: String,
+}
+
+impl EnvKey {
+    /// Read the key from the environment variable `var`.
+    pub fn new(var: impl Into<String>) -> Self {
+        EnvKey { var: var.into() }
+    }
+}
+
+impl Default for EnvKey {
+    fn default() -> Self {
+        EnvKey::new("SEALED_KEY")
+    }
+}
+
+impl KeyProvider for EnvKey {
+    fn provide(&self) -> Result<Key, Error> {
+        let mut b64 = env::var(&self.var)
+            .map_err(|_| Error::KeyUnavailable(format!("{} is not set", self.var)))?;
+
+        let key = Key::from_base64(&b64);
+        b64.zeroize();
+
+        key
+    }
+}
+
+/// Reads a base64 key from a file, ignoring trailing newlines.
+#[derive(Clone, Debug)]
+pub struct FileKey {
+    path: PathBuf,
+}
+
+impl FileKey {
+    /// Read the key from the file at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileKey { path: path.into() }
+    }
+}
+
+impl KeyProvider for FileKey {
+    fn provide(&self) -> Result<Key, Error> {
+        let raw = fs::read_to_string(&self.path).map_err(|e| {
+            Error::KeyUnavailable(format!(
+                "failed to read key file {}: {}",
+                self.path.display(),
+                e
+            ))
+        })?;
+
+        decode_output(raw)
+    }
+}
+
+/// Reads a base64 key from an inherited file descriptor, e.g. `3< key.txt` in a shell.
+#[cfg(unix)]
+#[derive(Clone, Debug)]
+pub struct FdKey {
+    fd: u32,
+}
+
+#[cfg(unix)]
+impl FdKey {
+    /// Read the key from file descriptor `fd`.
+    pub fn new(fd: u32) -> Self {
+        FdKey { fd }
+    }
+}
+
+#[cfg(unix)]
+impl KeyProvider for FdKey {
+    fn provide(&self) -> Result<Key, Error> {
+        let raw = fs::read_to_string(format!("/dev/fd/{}", self.fd)).map_err(|e| {
+            Error::KeyUnavailable(format!(
+                "failed to read key from file descriptor {}: {}",
+                self.fd, e
+            ))
+        })?;
+
+        decode_output(raw)
+    }
+}
+
+/// Runs a shell command and reads a base64 key from its stdout.
+///
+/// The command runs through `sh -c` (`cmd /C` on Windows) with stdin and stderr inherited,
+/// so interactive password managers such as `pass show project/sealed` work.
+#[derive(Clone, Debug)]
+pub struct CommandKey {
+    command: String,
+}
+
+impl CommandKey {
+    /// Run `command` to obtain the key.
+    pub fn new(command: impl Into<String>) -> Self {
+        CommandKey {
+            command: command.into(),
+        }
+    }
+}
+
+impl KeyProvider for CommandKey {
+    fn provide(&self) -> Result<Key, Error> {
+        let mut command = if cfg!(windows) {
+            let mut command = Command::new("cmd");
+            command.arg("/C");
+            command
+        } else {
+            let mut command = Command::new("sh");
+            command.arg("-c");
+            command
+        };
+
+        let output = command
+            .arg(&self.command)
+            .stdin(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| {
+                Error::KeyUnavailable(format!(
+                    "failed to run key command '{}': {}",
+                    self.command, e
+                ))
+            })?;
+
+        let mut stdout = output.stdout;
+
+        if !output.status.success() {
+            stdout.zeroize();
+            return Err(Error::KeyUnavailable(format!(
+                "key command '{}' failed with {}",
+                self.command, output.status
+            )));
+        }
+
+        let raw = String::from_utf8(stdout).map_err(|e| {
+            e.into_bytes().zeroize();
+            Error::InvalidKeyEncoding
+        })?;
+
+        decode_output(raw)
+    }
+}
+
+fn decode_output(mut raw: String) -> Result<Key, Error> {
+    let key = Key::from_base64(raw.trim_end_matches(['\n', '\r']));
+    raw.zeroize();
+
+    key
+}
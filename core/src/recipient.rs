@@ -0,0 +1,377 @@
+//! Recipients of envelope-encrypted values, and X25519 identities.
+//!
+//! An X25519 [`Recipient`] (public) is enough to encrypt a value; only the matching
+//! [`Identity`] (private) can decrypt it. Both are encoded as prefixed base64 strings:
+//! `sealed-recipient-<base64>` and `sealed-identity-<base64>`.
+//!
+//! A recipient can also be a symmetric key, named by its [`Key::id`] as `key:<id>`; wrapping
+//! a value for it requires the key itself.
+
+use base64::Engine as _;
+use base64::engine::general_purpose;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use hkdf::Hkdf;
+use rand::TryRngCore;
+use rand::rngs::OsRng;
+use secrecy::SecretString;
+use sha2::Sha256;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::error::Error;
+use crate::key::Key;
+
+const IDENTITY_PREFIX: &str = "sealed-identity-";
+const RECIPIENT_PREFIX: &str = "sealed-recipient-";
+const KEY_RECIPIENT_PREFIX: &str = "key:";
+const WRAP_INFO: &[u8] = b"sealed x25519 wrap v1";
+
+/// An X25519 private key that can decrypt values encrypted to its [`Recipient`].
+pub struct Identity(StaticSecret);
+
+impl Identity {
+    /// Generate a new random identity.
+    pub fn generate() -> Result<Self, Error> {
+        let mut bytes = Zeroizing::new([0u8; 32]);
+        OsRng
+            .try_fill_bytes(bytes.as_mut())
+            .map_err(|_| Error::Random)?;
+
+        Ok(Identity(StaticSecret::from(*bytes)))
+    }
+
+    /// Read an identity file: the first line that is not blank or a `#` comment.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let mut raw = fs::read_to_string(path).map_err(|e| {
+            Error::KeyUnavailable(format!(
+                "failed to read identity file {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+        let identity = raw
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .ok_or(Error::InvalidIdentity)
+            .and_then(Identity::from_str);
+        raw.zeroize();
+
+        identity
+    }
+
+    /// The public recipient for this identity.
+    pub fn to_recipient(&self) -> Recipient {
+        Recipient(Kind::X25519(PublicKey::from(&self.0)))
+    }
+
+    /// Encode the identity as `sealed-identity-<base64>`.
+    pub fn to_encoded(&self) -> SecretString {
+        SecretString::from(format!(
+            "{}{}",
+            IDENTITY_PREFIX,
+            general_purpose::STANDARD.encode(self.0.as_bytes())
+        ))
+    }
+
+    pub(crate) fn unwrap_key(&self, ephemeral: &[u8; 32], wrapped: &[u8]) -> Option<Key> {
+        let ephemeral = PublicKey::from(*ephemeral);
+        let shared = self.0.diffie_hellman(&ephemeral);
+        if !shared.was_contributory() {
+            return None;
+        }
+        let wrap_key = wrap_key(shared.as_bytes(), &ephemeral, &PublicKey::from(&self.0));
+
+        let cipher = ChaCha20Poly1305::new_from_slice(wrap_key.as_ref()).ok()?;
+        let data_key = Zeroizing::new(
+            cipher
+                .decrypt(
+                    Nonce::from_slice(&[0u8; 12]),
+                    Payload {
+                        msg: wrapped,
+                        aad: &[],
+                    },
+                )
+                .ok()?,
+        );
+
+        Key::from_bytes(&data_key).ok()
+    }
+}
+
+impl FromStr for Identity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = s
+            .strip_prefix(IDENTITY_PREFIX)
+            .and_then(|b64| general_purpose::STANDARD.decode(b64).ok())
+            .map(Zeroizing::new)
+            .ok_or(Error::InvalidIdentity)?;
+        let bytes: [u8; 32] = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| Error::InvalidIdentity)?;
+
+        Ok(Identity(StaticSecret::from(bytes)))
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Identity({})", self.to_recipient())
+    }
+}
+
+/// Someone a value can be encrypted to: an X25519 public key (`sealed-recipient-...`) or a
+/// symmetric key named by its id (`key:<id>`).
+#[derive(Clone, PartialEq, Eq)]
+pub struct Recipient(pub(crate) Kind);
+
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) enum Kind {
+    X25519(PublicKey),
+    Key(String),
+}
+
+impl Recipient {
+    /// The recipient for a symmetric key.
+    pub fn from_key(key: &Key) -> Self {
+        Recipient(Kind::Key(key.id()))
+    }
+
+    /// The key id, if this recipient is a symmetric key.
+    pub fn key_id(&self) -> Option<&str> {
+        match &self.0 {
+            Kind::Key(id) => Some(id),
+            Kind::X25519(_) => None,
+        }
+    }
+}
+
+/// Wrap `data_key` for an X25519 recipient, returning the ephemeral public key and the
+/// wrapped key.
+pub(crate) fn wrap_x25519(
+    recipient: &PublicKey,
+    data_key: &Key,
+) -> Result<([u8; 32], Vec<u8>), Error> {
+    let mut ephemeral_bytes = Zeroizing::new([0u8; 32]);
+    OsRng
+        .try_fill_bytes(ephemeral_bytes.as_mut())
+        .map_err(|_| Error::Random)?;
+
+    let ephemeral = StaticSecret::from(*ephemeral_bytes);
+    let ephemeral_public = PublicKey::from(&ephemeral);
+    let shared = ephemeral.diffie_hellman(recipient);
+    // A low-order public key forces a shared secret that does not depend on our ephemeral key.
+    if !shared.was_contributory() {
+        return Err(Error::InvalidRecipient(
+            Recipient(Kind::X25519(*recipient)).to_string(),
+        ));
+    }
+    let wrap_key = wrap_key(shared.as_bytes(), &ephemeral_public, recipient);
+
+    let cipher =
+        ChaCha20Poly1305::new_from_slice(wrap_key.as_ref()).map_err(|_| Error::InvalidKeyLength)?;
+    let wrapped = cipher
+        .encrypt(
+            Nonce::from_slice(&[0u8; 12]),
+            Payload {
+                msg: data_key.expose_secret(),
+                aad: &[],
+            },
+        )
+        .map_err(|_| Error::Encryption)?;
+
+    Ok((ephemeral_public.to_bytes(), wrapped))
+}
+
+impl FromStr for Recipient {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(id) = s.strip_prefix(KEY_RECIPIENT_PREFIX) {
+            let valid = !id.is_empty()
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || matches!(c, 'a'..='f'));
+            if !valid {
+                return Err(Error::InvalidRecipient(s.to_string()));
+            }
+            return Ok(Recipient(Kind::Key(id.to_string())));
+        }
+
+        let bytes: [u8; 32] = s
+            .strip_prefix(RECIPIENT_PREFIX)
+            .and_then(|b64| general_purpose::STANDARD.decode(b64).ok())
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| Error::InvalidRecipient(s.to_string()))?;
+
+        Ok(Recipient(Kind::X25519(PublicKey::from(bytes))))
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Kind::X25519(public) => write!(
+                f,
+                "{}{}",
+                RECIPIENT_PREFIX,
+                general_purpose::STANDARD.encode(public.as_bytes())
+            ),
+            Kind::Key(id) => write!(f, "{}{}", KEY_RECIPIENT_PREFIX, id),
+        }
+    }
+}
+
+impl fmt::Debug for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Recipient({})", self)
+    }
+}
+
+// A fresh ephemeral key is used for every wrap, so each derived wrap key encrypts exactly
+// one message and a fixed zero nonce is safe.
+fn wrap_key(
+    shared: &[u8; 32],
+    ephemeral: &PublicKey,
+    recipient: &PublicKey,
+) -> Zeroizing<[u8; 32]> {
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(ephemeral.as_bytes());
+    salt[32..].copy_from_slice(recipient.as_bytes());
+
+    let mut out = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(WRAP_INFO, out.as_mut())
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use secrecy::ExposeSecret;
+
+    #[test]
+    fn identity_round_trips() {
+        let identity = Identity::generate().unwrap();
+        let encoded = identity.to_encoded();
+        let parsed: Identity = encoded.expose_secret().parse().unwrap();
+
+        assert!(encoded.expose_secret().starts_with(IDENTITY_PREFIX));
+        assert_eq!(parsed.to_encoded().expose_secret(), encoded.expose_secret());
+        assert!(parsed.to_recipient() == identity.to_recipient());
+    }
+
+    #[test]
+    fn rejects_malformed_identities() {
+        let short = format!(
+            "{}{}",
+            IDENTITY_PREFIX,
+            general_purpose::STANDARD.encode([1u8; 16])
+        );
+        let recipient = Identity::generate().unwrap().to_recipient().to_string();
+
+        for identity in [
+            "",
+            "sealed-identity-",
+            "sealed-identity-***",
+            &short,
+            &recipient,
+        ] {
+            assert!(
+                matches!(identity.parse::<Identity>(), Err(Error::InvalidIdentity)),
+                "{}",
+                identity
+            );
+        }
+    }
+
+    #[test]
+    fn recipient_round_trips() {
+        let key = Key::from_bytes(&[1; 32]).unwrap();
+        for recipient in [
+            Identity::generate().unwrap().to_recipient(),
+            Recipient::from_key(&key),
+        ] {
+            let encoded = recipient.to_string();
+            assert!(
+                encoded.parse::<Recipient>().unwrap() == recipient,
+                "{}",
+                encoded
+            );
+        }
+
+        let key_recipient: Recipient = format!("key:{}", key.id()).parse().unwrap();
+        assert_eq!(key_recipient.key_id(), Some(key.id().as_str()));
+    }
+
+    #[test]
+    fn rejects_malformed_recipients() {
+        let short = format!(
+            "{}{}",
+            RECIPIENT_PREFIX,
+            general_purpose::STANDARD.encode([1u8; 16])
+        );
+
+        for recipient in [
+            "",
+            "key:",
+            "key:ABCD",
+            "key:xyz",
+            "sealed-recipient-***",
+            &short,
+        ] {
+            assert!(
+                matches!(
+                    recipient.parse::<Recipient>(),
+                    Err(Error::InvalidRecipient(_))
+                ),
+                "{}",
+                recipient
+            );
+        }
+    }
+
+    #[test]
+    fn wraps_for_the_matching_identity_only() {
+        let identity = Identity::generate().unwrap();
+        let Kind::X25519(public) = identity.to_recipient().0 else {
+            unreachable!()
+        };
+        let data_key = Key::from_bytes(&[7; 32]).unwrap();
+
+        let (ephemeral, wrapped) = wrap_x25519(&public, &data_key).unwrap();
+        let unwrapped = identity.unwrap_key(&ephemeral, &wrapped).unwrap();
+        assert_eq!(unwrapped.expose_secret(), data_key.expose_secret());
+
+        let other = Identity::generate().unwrap();
+        assert!(other.unwrap_key(&ephemeral, &wrapped).is_none());
+    }
+
+    #[test]
+    fn rejects_low_order_points() {
+        let data_key = Key::from_bytes(&[7; 32]).unwrap();
+        // u = 0 and u = 1 have small order and force an all-zero shared secret.
+        let mut one = [0u8; 32];
+        one[0] = 1;
+        for point in [[0u8; 32], one] {
+            assert!(matches!(
+                wrap_x25519(&PublicKey::from(point), &data_key),
+                Err(Error::InvalidRecipient(_))
+            ));
+
+            let identity = Identity::generate().unwrap();
+            assert!(identity.unwrap_key(&point, &[0u8; 48]).is_none());
+        }
+    }
+}
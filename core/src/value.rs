@@ -0,0 +1,648 @@
+use base64::Engine as _;
+use base64::engine::general_purpose;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use rand::TryRngCore;
+use rand::rngs::OsRng;
+use secrecy::{ExposeSecret, SecretSlice, SecretString};
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::envelope::Envelope;
+use crate::error::Error;
+use crate::key::Key;
+use crate::keyring::Keyring;
+use crate::recipient::Recipient;
+
+const PREFIX_V1: &str = "ENCv1";
+const PREFIX_V2: &str = "ENCv2";
+const PREFIX_V3: &str = "ENCv3";
+const PREFIXES: &[&str] = &["ENCv1:", "ENCv2:", "ENCv3:", "ENCr1:", "ENCr2:"];
+
+/// Algorithm id written into `ENCv2` and `ENCv3` values.
+const ALGORITHM: &str = "chacha20poly1305";
+
+pub(crate) const NONCE_LEN: usize = 12;
+
+/// A value encrypted with a symmetric key.
+///
+/// New values use `ENCv2:<algorithm>:<key id>:<base64(nonce)>:<base64(ciphertext)>`, which
+/// records the [`Key::id`] of the key that encrypted it, or
+/// `ENCv3:<algorithm>:<key id>:<context>:<base64(nonce)>:<base64(ciphertext)>` when the value
+/// is bound to a context (an environment name such as `production`). The older
+/// `ENCv1:<base64(nonce)>:<base64(ciphertext)>` format carries no key id and is still read.
+///
+/// The variable name, and the context if any, are authenticated as AAD, so a value only
+/// decrypts under the name and context it was encrypted for.
+///
+/// Parse with [`FromStr`] and encode with [`Display`](fmt::Display).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncryptedValue {
+    key_id: Option<String>,
+    context: Option<String>,
+    nonce: [u8; NONCE_LEN],
+    ciphertext: Vec<u8>,
+}
+
+impl EncryptedValue {
+    /// Encrypt `plaintext` with a fresh random nonce, binding it to `context` and `var_name`.
+    pub fn encrypt(
+        key: &Key,
+        context: Option<&str>,
+        var_name: &str,
+        plaintext: &[u8],
+    ) -> Result<Self, Error> {
+        if let Some(context) = context {
+            validate_context(context)?;
+        }
+
+        let nonce = random_nonce()?;
+        let ciphertext = seal(key, &nonce, &aad(context, var_name), plaintext)?;
+
+        Ok(EncryptedValue {
+            key_id: Some(key.id()),
+            context: context.map(str::to_string),
+            nonce,
+            ciphertext,
+        })
+    }
+
+    /// Decrypt the value, verifying it was encrypted for `context` and `var_name`.
+    ///
+    /// If the value records a different context, this fails with [`Error::ContextMismatch`];
+    /// if it records a key id that does not match `key`, with [`Error::KeyMismatch`]. Neither
+    /// attempts decryption.
+    pub fn decrypt(
+        &self,
+        key: &Key,
+        context: Option<&str>,
+        var_name: &str,
+    ) -> Result<SecretSlice<u8>, Error> {
+        check_context(self.context.as_deref(), context)?;
+
+        if let Some(expected) = &self.key_id {
+            let provided = key.id();
+            if *expected != provided {
+                return Err(Error::KeyMismatch {
+                    expected: expected.clone(),
+                    provided,
+                });
+            }
+        }
+
+        open(
+            key,
+            &self.nonce,
+            &aad(self.context.as_deref(), var_name),
+            &self.ciphertext,
+        )
+    }
+
+    /// Decrypt the value and require the plaintext to be valid UTF-8.
+    pub fn decrypt_string(
+        &self,
+        key: &Key,
+        context: Option<&str>,
+        var_name: &str,
+    ) -> Result<SecretString, Error> {
+        to_utf8(self.decrypt(key, context, var_name)?)
+    }
+
+    /// The id of the key that encrypted the value, or `None` for `ENCv1` values.
+    pub fn key_id(&self) -> Option<&str> {
+        self.key_id.as_deref()
+    }
+
+    /// The context the value is bound to, or `None` for `ENCv1` and `ENCv2` values.
+    pub fn context(&self) -> Option<&str> {
+        self.context.as_deref()
+    }
+
+    /// The 12-byte nonce.
+    pub fn nonce(&self) -> &[u8] {
+        &self.nonce
+    }
+
+    /// The ciphertext including the Poly1305 tag.
+    pub fn ciphertext(&self) -> &[u8] {
+        &self.ciphertext
+    }
+}
+
+impl FromStr for EncryptedValue {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.split(':');
+
+        let prefix = parts.next();
+        let mut fields: Vec<&str> = parts.collect();
+
+        let (key_id, context) = match (prefix, fields.len()) {
+            (Some(PREFIX_V1), 2) => (None, None),
+            (Some(PREFIX_V2), 4) | (Some(PREFIX_V3), 5) => {
+                let alg = fields.remove(0);
+                if alg != ALGORITHM {
+                    return Err(Error::UnsupportedAlgorithm(alg.to_string()));
+                }
+
+                let key_id = fields.remove(0);
+                if key_id.is_empty() {
+                    return Err(Error::InvalidFormat);
+                }
+
+                let context = if prefix == Some(PREFIX_V3) {
+                    let context = fields.remove(0);
+                    validate_context(context).map_err(|_| Error::InvalidFormat)?;
+                    Some(context.to_string())
+                } else {
+                    None
+                };
+
+                (Some(key_id.to_string()), context)
+            }
+            _ => return Err(Error::InvalidFormat),
+        };
+        let (nonce_b64, ct_b64) = (fields[0], fields[1]);
+
+        let nonce = general_purpose::STANDARD
+            .decode(nonce_b64)
+            .map_err(|_| Error::InvalidNonceEncoding)?
+            .try_into()
+            .map_err(|_| Error::InvalidNonceLength)?;
+
+        let ciphertext = general_purpose::STANDARD
+            .decode(ct_b64)
+            .map_err(|_| Error::InvalidCiphertextEncoding)?;
+
+        Ok(EncryptedValue {
+            key_id,
+            context,
+            nonce,
+            ciphertext,
+        })
+    }
+}
+
+impl fmt::Display for EncryptedValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.key_id, &self.context) {
+            (Some(key_id), Some(context)) => {
+                write!(f, "{}:{}:{}:{}:", PREFIX_V3, ALGORITHM, key_id, context)?
+            }
+            (Some(key_id), None) => write!(f, "{}:{}:{}:", PREFIX_V2, ALGORITHM, key_id)?,
+            (None, _) => write!(f, "{}:", PREFIX_V1)?,
+        }
+
+        write!(
+            f,
+            "{}:{}",
+            general_purpose::STANDARD.encode(self.nonce),
+            general_purpose::STANDARD.encode(&self.ciphertext)
+        )
+    }
+}
+
+/// Any sealed value, dispatched on its format prefix.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SealedValue {
+    /// `ENCv3:`, `ENCv2:` or `ENCv1:`, encrypted with a symmetric key.
+    Symmetric(EncryptedValue),
+    /// `ENCr2:` or `ENCr1:`, encrypted to one or more recipients.
+    Recipients(Envelope),
+}
+
+impl SealedValue {
+    /// Decrypt the value with whichever key or identity in `keyring` applies, verifying it
+    /// was encrypted for `context` and `var_name`.
+    pub fn decrypt(
+        &self,
+        keyring: &Keyring,
+        context: Option<&str>,
+        var_name: &str,
+    ) -> Result<SecretSlice<u8>, Error> {
+        match self {
+            SealedValue::Symmetric(value) => {
+                check_context(value.context(), context)?;
+
+                let keys = keyring.keys();
+                if keys.is_empty() {
+                    return Err(Error::NoKey);
+                }
+
+                match value.key_id() {
+                    Some(id) => match keyring.key_by_id(id) {
+                        Some(key) => value.decrypt(key, context, var_name),
+                        None => Err(Error::KeyMismatch {
+                            expected: id.to_string(),
+                            provided: keys.iter().map(Key::id).collect::<Vec<_>>().join(", "),
+                        }),
+                    },
+                    // ENCv1 values do not say which key encrypted them; try each one.
+                    None => keys
+                        .iter()
+                        .find_map(|key| value.decrypt(key, context, var_name).ok())
+                        .ok_or(Error::Decryption),
+                }
+            }
+            SealedValue::Recipients(envelope) => envelope.decrypt(keyring, context, var_name),
+        }
+    }
+
+    /// Decrypt the value and require the plaintext to be valid UTF-8.
+    pub fn decrypt_string(
+        &self,
+        keyring: &Keyring,
+        context: Option<&str>,
+        var_name: &str,
+    ) -> Result<SecretString, Error> {
+        to_utf8(self.decrypt(keyring, context, var_name)?)
+    }
+
+    /// The context the value is bound to, if any.
+    pub fn context(&self) -> Option<&str> {
+        match self {
+            SealedValue::Symmetric(value) => value.context(),
+            SealedValue::Recipients(envelope) => envelope.context(),
+        }
+    }
+}
+
+impl FromStr for SealedValue {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.split_once(':').map(|(prefix, _)| prefix) {
+            Some("ENCr1" | "ENCr2") => value.parse().map(SealedValue::Recipients),
+            _ => value.parse().map(SealedValue::Symmetric),
+        }
+    }
+}
+
+impl fmt::Display for SealedValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SealedValue::Symmetric(value) => value.fmt(f),
+            SealedValue::Recipients(envelope) => envelope.fmt(f),
+        }
+    }
+}
+
+/// Encrypt a UTF-8 plaintext and return it encoded as `ENCv2:...`, or `ENCv3:...` when
+/// bound to a `context`.
+pub fn encrypt_value(
+    key: &Key,
+    context: Option<&str>,
+    var_name: &str,
+    plaintext: &SecretString,
+) -> Result<String, Error> {
+    EncryptedValue::encrypt(key, context, var_name, plaintext.expose_secret().as_bytes())
+        .map(|value| value.to_string())
+}
+
+/// Encrypt a UTF-8 plaintext to `recipients` and return it encoded as `ENCr1:...`, or
+/// `ENCr2:...` when bound to a `context`.
+///
+/// Key recipients (`key:<id>`) are wrapped with the matching key from `keyring`.
+pub fn encrypt_value_to(
+    recipients: &[Recipient],
+    keyring: &Keyring,
+    context: Option<&str>,
+    var_name: &str,
+    plaintext: &SecretString,
+) -> Result<String, Error> {
+    Envelope::encrypt(
+        recipients,
+        keyring,
+        context,
+        var_name,
+        plaintext.expose_secret().as_bytes(),
+    )
+    .map(|value| value.to_string())
+}
+
+/// Parse and decrypt an `ENCv3:...`, `ENCv2:...` or `ENCv1:...` value into UTF-8 plaintext.
+pub fn decrypt_value(
+    key: &Key,
+    context: Option<&str>,
+    var_name: &str,
+    encrypted: &str,
+) -> Result<SecretString, Error> {
+    encrypted
+        .parse::<EncryptedValue>()?
+        .decrypt_string(key, context, var_name)
+}
+
+/// Returns `true` if `value` looks like a sealed value.
+pub fn is_encrypted(value: &str) -> bool {
+    PREFIXES.iter().any(|prefix| value.starts_with(prefix))
+}
+
+/// Check that a context name can be embedded in a value: ASCII letters, digits, `.`, `_`
+/// and `-` only.
+pub(crate) fn validate_context(context: &str) -> Result<(), Error> {
+    let valid = !context.is_empty()
+        && context
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidContext(context.to_string()))
+    }
+}
+
+pub(crate) fn check_context(found: Option<&str>, expected: Option<&str>) -> Result<(), Error> {
+    if found == expected {
+        return Ok(());
+    }
+
+    Err(Error::ContextMismatch {
+        found: found.unwrap_or("(none)").to_string(),
+        expected: expected.unwrap_or("(none)").to_string(),
+    })
+}
+
+/// The AAD for a value: the variable name, prefixed by the context when there is one.
+pub(crate) fn aad<'a>(context: Option<&str>, var_name: &'a str) -> Cow<'a, str> {
+    match context {
+        Some(context) => Cow::Owned(format!("{}\0{}", context, var_name)),
+        None => Cow::Borrowed(var_name),
+    }
+}
+
+pub(crate) fn random_nonce() -> Result<[u8; NONCE_LEN], Error> {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng
+        .try_fill_bytes(&mut nonce)
+        .map_err(|_| Error::Random)?;
+
+    Ok(nonce)
+}
+
+pub(crate) fn seal(
+    key: &Key,
+    nonce: &[u8; NONCE_LEN],
+    aad: &str,
+    plaintext: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let cipher = ChaCha20Poly1305::new_from_slice(key.expose_secret())
+        .map_err(|_| Error::InvalidKeyLength)?;
+
+    cipher
+        .encrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: plaintext,
+                aad: aad.as_bytes(),
+            },
+        )
+        .map_err(|_| Error::Encryption)
+}
+
+pub(crate) fn open(
+    key: &Key,
+    nonce: &[u8; NONCE_LEN],
+    aad: &str,
+    ciphertext: &[u8],
+) -> Result<SecretSlice<u8>, Error> {
+    let cipher = ChaCha20Poly1305::new_from_slice(key.expose_secret())
+        .map_err(|_| Error::InvalidKeyLength)?;
+
+    let plaintext = cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: aad.as_bytes(),
+            },
+        )
+        .map_err(|_| Error::Decryption)?;
+
+    Ok(SecretSlice::from(plaintext))
+}
+
+pub(crate) fn to_utf8(decrypted: SecretSlice<u8>) -> Result<SecretString, Error> {
+    let plaintext = str::from_utf8(decrypted.expose_secret()).map_err(|_| Error::InvalidUtf8)?;
+
+    Ok(SecretString::from(plaintext))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> Key {
+        Key::from_bytes(&[byte; 32]).unwrap()
+    }
+
+    fn decrypt(key: &Key, context: Option<&str>, value: &str) -> Result<String, Error> {
+        decrypt_value(key, context, "SECRET", value)
+            .map(|plaintext| plaintext.expose_secret().to_string())
+    }
+
+    #[test]
+    fn v2_round_trips() {
+        let key = key(1);
+        let value = encrypt_value(&key, None, "SECRET", &SecretString::from("hunter2")).unwrap();
+        let parsed: EncryptedValue = value.parse().unwrap();
+
+        assert!(value.starts_with(&format!("ENCv2:chacha20poly1305:{}:", key.id())));
+        assert_eq!(parsed.key_id(), Some(key.id().as_str()));
+        assert_eq!(parsed.context(), None);
+        assert_eq!(parsed.to_string(), value);
+        assert_eq!(decrypt(&key, None, &value).unwrap(), "hunter2");
+        assert!(is_encrypted(&value));
+    }
+
+    #[test]
+    fn v1_round_trips() {
+        let key = key(1);
+        let v2: EncryptedValue = encrypt_value(&key, None, "SECRET", &SecretString::from("x"))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let v1 = format!(
+            "ENCv1:{}:{}",
+            general_purpose::STANDARD.encode(v2.nonce()),
+            general_purpose::STANDARD.encode(v2.ciphertext())
+        );
+
+        assert_eq!(v1.parse::<EncryptedValue>().unwrap().to_string(), v1);
+        assert_eq!(v1.parse::<EncryptedValue>().unwrap().key_id(), None);
+        assert_eq!(decrypt(&key, None, &v1).unwrap(), "x");
+    }
+
+    #[test]
+    fn v2_reports_a_different_key() {
+        let value = encrypt_value(&key(1), None, "SECRET", &SecretString::from("x")).unwrap();
+
+        match decrypt(&key(2), None, &value) {
+            Err(Error::KeyMismatch { expected, provided }) => {
+                assert_eq!(expected, key(1).id());
+                assert_eq!(provided, key(2).id());
+            }
+            other => panic!("expected KeyMismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn v2_rejects_a_tampered_value_or_another_name() {
+        let key = key(1);
+        let value: EncryptedValue = encrypt_value(&key, None, "SECRET", &SecretString::from("x"))
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        let mut tampered = value.clone();
+        tampered.ciphertext[0] ^= 1;
+        assert!(matches!(
+            tampered.decrypt(&key, None, "SECRET"),
+            Err(Error::Decryption)
+        ));
+        assert!(matches!(
+            value.decrypt(&key, None, "OTHER"),
+            Err(Error::Decryption)
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_and_unknown_values() {
+        let value = encrypt_value(&key(1), None, "SECRET", &SecretString::from("x")).unwrap();
+        let (head, ciphertext) = value.rsplit_once(':').unwrap();
+        let (_, nonce) = head.rsplit_once(':').unwrap();
+
+        for (malformed, expected) in [
+            (head.to_string(), Error::InvalidFormat),
+            (format!("{}:***", head), Error::InvalidCiphertextEncoding),
+            (
+                format!("{}:{}:extra", head, ciphertext),
+                Error::InvalidFormat,
+            ),
+            (value.replacen("ENCv2", "ENCv9", 1), Error::InvalidFormat),
+            (value.replacen("ENCv2", "encv2", 1), Error::InvalidFormat),
+            (format!("ENCv1:{}", ciphertext), Error::InvalidFormat),
+            (
+                format!("ENCv2:chacha20poly1305::{}:{}", nonce, ciphertext),
+                Error::InvalidFormat,
+            ),
+            (
+                value.replacen("chacha20poly1305", "aes256gcm", 1),
+                Error::UnsupportedAlgorithm("aes256gcm".to_string()),
+            ),
+            (value.replacen(nonce, "AAAA", 1), Error::InvalidNonceLength),
+            (value.replacen(nonce, "***", 1), Error::InvalidNonceEncoding),
+            ("plain".to_string(), Error::InvalidFormat),
+        ] {
+            let err = malformed.parse::<EncryptedValue>().unwrap_err();
+            assert_eq!(err.to_string(), expected.to_string(), "{}", malformed);
+        }
+
+        // A ciphertext cut short still parses, but no longer authenticates.
+        let bytes = general_purpose::STANDARD.decode(ciphertext).unwrap();
+        for len in [0, bytes.len() - 1] {
+            let truncated = format!(
+                "{}:{}",
+                head,
+                general_purpose::STANDARD.encode(&bytes[..len])
+            );
+            assert!(matches!(
+                decrypt(&key(1), None, &truncated),
+                Err(Error::Decryption)
+            ));
+        }
+    }
+
+    #[test]
+    fn v3_round_trips_in_its_context() {
+        let key = key(1);
+        let value = encrypt_value(
+            &key,
+            Some("production"),
+            "SECRET",
+            &SecretString::from("hunter2"),
+        )
+        .unwrap();
+        let parsed: EncryptedValue = value.parse().unwrap();
+
+        assert!(value.starts_with(&format!("ENCv3:chacha20poly1305:{}:production:", key.id())));
+        assert_eq!(parsed.context(), Some("production"));
+        assert_eq!(parsed.to_string(), value);
+        assert_eq!(
+            decrypt(&key, Some("production"), &value).unwrap(),
+            "hunter2"
+        );
+    }
+
+    #[test]
+    fn v3_reports_another_context() {
+        let key = key(1);
+        let value =
+            encrypt_value(&key, Some("production"), "SECRET", &SecretString::from("x")).unwrap();
+
+        for context in [Some("staging"), None] {
+            assert!(matches!(
+                decrypt(&key, context, &value),
+                Err(Error::ContextMismatch { .. })
+            ));
+        }
+
+        // An ENCv2 value read in a context is a mismatch too.
+        let v2 = encrypt_value(&key, None, "SECRET", &SecretString::from("x")).unwrap();
+        match decrypt(&key, Some("production"), &v2) {
+            Err(Error::ContextMismatch { found, expected }) => {
+                assert_eq!(found, "(none)");
+                assert_eq!(expected, "production");
+            }
+            other => panic!("expected ContextMismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn v3_cannot_be_relabelled_or_downgraded() {
+        let key = key(1);
+        let value =
+            encrypt_value(&key, Some("production"), "SECRET", &SecretString::from("x")).unwrap();
+
+        let relabelled = value.replacen(":production:", ":staging:", 1);
+        assert!(matches!(
+            decrypt(&key, Some("staging"), &relabelled),
+            Err(Error::Decryption)
+        ));
+
+        // Dropping the context turns the value into a well-formed ENCv2 value whose AAD no
+        // longer matches.
+        let downgraded = value
+            .replacen("ENCv3", "ENCv2", 1)
+            .replacen(":production:", ":", 1);
+        assert!(downgraded.parse::<EncryptedValue>().is_ok());
+        assert!(matches!(
+            decrypt(&key, None, &downgraded),
+            Err(Error::Decryption)
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_contexts() {
+        let key = key(1);
+        for context in ["", "prod env", "prod:env", "prod\0"] {
+            assert!(matches!(
+                encrypt_value(&key, Some(context), "SECRET", &SecretString::from("x")),
+                Err(Error::InvalidContext(_))
+            ));
+        }
+
+        let value =
+            encrypt_value(&key, Some("production"), "SECRET", &SecretString::from("x")).unwrap();
+        assert!(matches!(
+            value
+                .replacen(":production:", ":prod env:", 1)
+                .parse::<EncryptedValue>(),
+            Err(Error::InvalidFormat)
+        ));
+        assert_eq!(aad(Some("production"), "SECRET"), "production\0SECRET");
+        assert_eq!(aad(None, "SECRET"), "SECRET");
+    }
+}
@@ -1,15 +1,20 @@
 //! Read and decrypt sealed environment variables.
 //!
 //! This crate mirrors the ergonomics of `std::env::var`, but understands values stored
-//! in the `ENCv1:<base64(nonce)>:<base64(ciphertext)>` format. If a value is encrypted,
-//! `SEALED_KEY` must be present in the environment for decryption.
+//! in the `ENCv2:<algorithm>:<key id>:<base64(nonce)>:<base64(ciphertext)>` format (and the
+//! older `ENCv1:<base64(nonce)>:<base64(ciphertext)>`). If a value is encrypted,
+//! `SEALED_KEY` (or `SEALED_KEYS`, several keys during a rotation, or `SEALED_PASSPHRASE`)
+//! must be present in the environment for decryption. Values encrypted to X25519 recipients
+//! (`ENCr1:`) are decrypted with `SEALED_IDENTITY` instead.
 //!
 //! # Quick start
 //! ```rust,no_run
 //! use sealed_env::{var, var_or_plain, var_optional};
 //!
-//! std::env::set_var("SEALED_KEY", "<base64-key>");
-//! std::env::set_var("DATABASE_PASSWORD", "ENCv1:...:...");
+//! unsafe {
+//!     std::env::set_var("SEALED_KEY", "<base64-key>");
+//!     std::env::set_var("DATABASE_PASSWORD", "ENCv2:...");
+//! }
 //!
 //! let secret = var("DATABASE_PASSWORD")?;
 //! let maybe_plain = var_or_plain("MAYBE_PLAINTEXT")?;
@@ -21,29 +26,115 @@
 //! - `var`: requires the variable to be present and encrypted.
 //! - `var_or_plain`: returns plaintext as-is if it is not encrypted.
 //! - `var_optional`: returns `Ok(None)` if not set; otherwise decrypts if needed.
-use base64::Engine as _;
-use base64::engine::general_purpose;
-use chacha20poly1305::aead::{Aead, KeyInit, Payload};
-use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
-use secrecy::{ExposeSecret, SecretSlice, SecretString};
+//! - `from_path`: parses a `.env` file and returns its decrypted variables.
+//! - `load` / `load_path`: like `from_path`, but populate the process environment
+//!   without overwriting variables that are already set.
+//!
+//! # Secret values
+//! `var_secret`, `var_or_plain_secret` and `var_optional_secret` return [`SecretString`]
+//! instead of `String`: the plaintext is zeroized on drop and redacted from `Debug` output.
+//! ```rust,no_run
+//! use sealed_env::{ExposeSecret, var_secret};
+//!
+//! let secret = var_secret("DATABASE_PASSWORD")?;
+//! connect(secret.expose_secret());
+//! # fn connect(_: &str) {}
+//! # Ok::<(), sealed_env::SealedEnvError>(())
+//! ```
+//!
+//! # Explicit keys
+//! The free functions read and decode `SEALED_KEY` on every call. To decode the key once,
+//! or to take it from somewhere other than the environment, construct a [`Sealed`]:
+//! ```rust,no_run
+//! use sealed_env::Sealed;
+//!
+//! let sealed = Sealed::from_key_file("/run/secrets/sealed.key")?;
+//! let secret = sealed.var("DATABASE_PASSWORD")?;
+//! # Ok::<(), sealed_env::SealedEnvError>(())
+//! ```
+//!
+//! During a key rotation, [`Sealed::from_keyring_file`] (or `SEALED_KEYS`) holds both the old
+//! and the new key; each value is decrypted with the key whose id it records.
+//!
+//! Values written with a context (`sealed set --context production`, or a `[contexts]` entry
+//! in `.sealed.toml`) only decrypt in that context: set `SEALED_CONTEXT` or use
+//! [`Sealed::with_context`].
+//!
+//! # Strict mode
+//! [`Sealed::with_policy`] enforces the `[policy]` table of `.sealed.toml`: variables it
+//! requires to be encrypted are rejected if they are plaintext, even through `var_or_plain`
+//! and [`Sealed::read_path`], and variables it requires to stay plain are rejected if they
+//! are encrypted.
+//! ```rust,no_run
+//! use sealed_env::{Config, Sealed};
+//!
+//! let policy = Config::load(".sealed.toml".as_ref())?.policy;
+//! let sealed = Sealed::from_env()?.with_policy(policy);
+//! let vars = sealed.read_path(".env")?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [`Sealed::deny_duplicates`] also rejects env files that assign a variable more than once,
+//! rather than letting the last assignment win (`sealed dedupe` removes the extra ones), and
+//! [`Sealed::require_mac`] rejects env files without a `# sealed-mac:` trailer, as the CLI
+//! does when the project config sets `mac = true`.
+//!
+//! Keys can come from any [`provider::KeyProvider`]: the built-in environment, file,
+//! file-descriptor, command and passphrase providers, or your own implementation.
+use sealed_core::dotenv::{duplicates, parse_vars};
+use sealed_core::is_encrypted;
+use sealed_core::mac::FileMac;
+use sealed_core::policy::Rule;
+use std::collections::HashMap;
 use std::env;
+use std::fs;
+use std::path::Path;
 use thiserror::Error;
 
+pub use sealed::Sealed;
+pub use sealed_core::Error as CryptoError;
+pub use sealed_core::config::Config;
+pub use sealed_core::passphrase::PassphraseParams;
+pub use sealed_core::policy::Policy;
+pub use sealed_core::{Identity, Key, Keyring, Recipient};
+pub use secrecy::{ExposeSecret, SecretString};
+
+/// Key sources for [`Sealed::from_provider`].
+pub mod provider {
+    pub use sealed_core::passphrase::PassphraseKey;
+    #[cfg(unix)]
+    pub use sealed_core::provider::FdKey;
+    pub use sealed_core::provider::{CommandKey, EnvKey, FileKey, KeyProvider};
+}
+
+mod sealed;
+
 /// Errors returned by `sealed-env`.
 #[derive(Debug, Error)]
 pub enum SealedEnvError {
     /// The requested environment variable is not set.
     #[error("{0}")]
     MissingVar(String),
-    /// `SEALED_KEY` is missing from the environment.
+    /// None of `SEALED_KEY`, `SEALED_KEYS`, `SEALED_PASSPHRASE` or `SEALED_IDENTITY` is set, or
+    /// a key provider could not produce a key.
     #[error("{0}")]
     MissingKey(String),
-    /// The variable is set but does not start with `ENCv1:`.
+    /// The variable is set but is not an encrypted value.
     #[error("{0}")]
     NotEncrypted(String),
     /// Any cryptographic or decoding error.
+    #[error(transparent)]
+    Crypto(#[from] CryptoError),
+    /// The env file could not be read.
+    #[error("{0}")]
+    EnvFile(String),
+    /// The variable is encrypted, but the policy passed to [`Sealed::with_policy`] requires
+    /// it to stay plain.
+    #[error("{0}")]
+    PolicyViolation(String),
+    /// The env file assigns a variable more than once, and [`Sealed::deny_duplicates`] is set.
     #[error("{0}")]
-    Crypto(String),
+    DuplicateVar(String),
 }
 
 /// Read an encrypted variable from the process environment.
@@ -55,32 +146,30 @@ pub enum SealedEnvError {
 /// ```rust,no_run
 /// use sealed_env::var;
 ///
-/// std::env::set_var("SEALED_KEY", "<base64-key>");
-/// std::env::set_var("DATABASE_PASSWORD", "ENCv1:...:...");
+/// unsafe {
+///     std::env::set_var("SEALED_KEY", "<base64-key>");
+///     std::env::set_var("DATABASE_PASSWORD", "ENCv2:...");
+/// }
 ///
 /// let value = var("DATABASE_PASSWORD")?;
 /// # Ok::<(), sealed_env::SealedEnvError>(())
 /// ```
 pub fn var(name: &str) -> Result<String, SealedEnvError> {
-    let value = env::var(name).map_err(|_| {
-        SealedEnvError::MissingVar(format!("environment variable '{}' is not set", name))
-    })?;
-
-    if !is_encrypted(&value) {
-        return Err(SealedEnvError::NotEncrypted(format!(
-            "environment variable '{}' is not encrypted",
-            name
-        )));
-    }
-
-    let key_b64 = env::var("SEALED_KEY")
-        .map_err(|_| SealedEnvError::MissingKey("SEALED_KEY is not set".to_string()))?;
-
-    let key = decode_key(&SecretString::from(key_b64))?;
-    let decrypted = decrypt_value(&key, name, &value)?;
+    var_secret(name).map(expose)
+}
 
-    String::from_utf8(decrypted.expose_secret().to_vec())
-        .map_err(|_| SealedEnvError::Crypto("decrypted value is not valid UTF-8".to_string()))
+/// Like [`var`], but returns the plaintext as a [`SecretString`].
+///
+/// # Examples
+/// ```rust,no_run
+/// use sealed_env::{ExposeSecret, var_secret};
+///
+/// let value = var_secret("DATABASE_PASSWORD")?;
+/// let password: &str = value.expose_secret();
+/// # Ok::<(), sealed_env::SealedEnvError>(())
+/// ```
+pub fn var_secret(name: &str) -> Result<SecretString, SealedEnvError> {
+    var_with(name, decrypt_env)
 }
 
 /// Read a variable and return plaintext as-is if it is not encrypted.
@@ -89,29 +178,21 @@ pub fn var(name: &str) -> Result<String, SealedEnvError> {
 /// ```rust,no_run
 /// use sealed_env::var_or_plain;
 ///
-/// std::env::set_var("SEALED_KEY", "<base64-key>");
-/// std::env::set_var("FEATURE_FLAG", "true");
+/// unsafe {
+///     std::env::set_var("SEALED_KEY", "<base64-key>");
+///     std::env::set_var("FEATURE_FLAG", "true");
+/// }
 ///
 /// let value = var_or_plain("FEATURE_FLAG")?;
 /// # Ok::<(), sealed_env::SealedEnvError>(())
 /// ```
 pub fn var_or_plain(name: &str) -> Result<String, SealedEnvError> {
-    let value = env::var(name).map_err(|_| {
-        SealedEnvError::MissingVar(format!("environment variable '{}' is not set", name))
-    })?;
-
-    if !is_encrypted(&value) {
-        return Ok(value);
-    }
-
-    let key_b64 = env::var("SEALED_KEY")
-        .map_err(|_| SealedEnvError::MissingKey("SEALED_KEY is not set".to_string()))?;
-
-    let key = decode_key(&SecretString::from(key_b64))?;
-    let decrypted = decrypt_value(&key, name, &value)?;
+    var_or_plain_secret(name).map(expose)
+}
 
-    String::from_utf8(decrypted.expose_secret().to_vec())
-        .map_err(|_| SealedEnvError::Crypto("decrypted value is not valid UTF-8".to_string()))
+/// Like [`var_or_plain`], but returns the value as a [`SecretString`].
+pub fn var_or_plain_secret(name: &str) -> Result<SecretString, SealedEnvError> {
+    var_or_plain_with(name, decrypt_env)
 }
 
 /// Read a variable, returning `Ok(None)` if it is not set.
@@ -123,12 +204,209 @@ pub fn var_or_plain(name: &str) -> Result<String, SealedEnvError> {
 /// ```rust,no_run
 /// use sealed_env::var_optional;
 ///
-/// std::env::set_var("SEALED_KEY", "<base64-key>");
+/// unsafe { std::env::set_var("SEALED_KEY", "<base64-key>") };
 ///
 /// let value = var_optional("OPTIONAL_SECRET")?;
 /// # Ok::<(), sealed_env::SealedEnvError>(())
 /// ```
 pub fn var_optional(name: &str) -> Result<Option<String>, SealedEnvError> {
+    var_optional_secret(name).map(|value| value.map(expose))
+}
+
+/// Like [`var_optional`], but returns the value as a [`SecretString`].
+pub fn var_optional_secret(name: &str) -> Result<Option<SecretString>, SealedEnvError> {
+    var_optional_with(name, decrypt_env)
+}
+
+/// Parse a `.env` file and return its variables, decrypting encrypted values.
+///
+/// The file is parsed the same way as by the `sealed` CLI (see [`sealed_core::dotenv`]):
+/// `[export ]KEY=VALUE`, with blank lines and `#` comments ignored, inline comments after
+/// unquoted values stripped, and single- or double-quoted values that may span several lines.
+/// If a key appears more than once, the last occurrence wins.
+/// The key variables read by [`Sealed::from_env`] are read from the process environment
+/// only if the file contains encrypted values or a `# sealed-mac:` trailer. A file whose
+/// variables do not match its trailer is rejected with [`sealed_core::Error::MacMismatch`].
+///
+/// # Examples
+/// ```rust,no_run
+/// use sealed_env::from_path;
+///
+/// let vars = from_path(".env")?;
+/// let password = &vars["DATABASE_PASSWORD"];
+/// # Ok::<(), sealed_env::SealedEnvError>(())
+/// ```
+pub fn from_path(path: impl AsRef<Path>) -> Result<HashMap<String, String>, SealedEnvError> {
+    from_path_with(path.as_ref(), None)
+}
+
+/// Read `path` with `sealed`, or with a decryptor from the environment created on first use.
+fn from_path_with(
+    path: &Path,
+    sealed: Option<&Sealed>,
+) -> Result<HashMap<String, String>, SealedEnvError> {
+    let content = fs::read_to_string(path).map_err(|e| {
+        SealedEnvError::EnvFile(format!("failed to read env file {}: {}", path.display(), e))
+    })?;
+
+    let parsed = parse_vars(&content);
+    if sealed.is_some_and(Sealed::denies_duplicates)
+        && let Some(duplicate) = duplicates(&parsed).into_iter().next()
+    {
+        return Err(SealedEnvError::DuplicateVar(format!(
+            "variable '{}' is assigned more than once in {} (lines {})",
+            duplicate.key,
+            path.display(),
+            duplicate
+                .lines
+                .iter()
+                .map(usize::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+
+    let mut from_env = None;
+    let mut vars = HashMap::new();
+
+    match FileMac::find(&content)? {
+        Some(mac) => sealed_or_env(sealed, &mut from_env)?.verify_mac(&mac, &content)?,
+        None if sealed.is_some_and(Sealed::requires_mac) => {
+            return Err(CryptoError::MissingMac.into());
+        }
+        None => {}
+    }
+
+    for var in parsed {
+        let value = if is_encrypted(&var.value) {
+            sealed_or_env(sealed, &mut from_env)?.decrypt(&var.key, &var.value)?
+        } else if sealed.and_then(|sealed| sealed.rule(&var.key)) == Some(Rule::Encrypted) {
+            return Err(SealedEnvError::NotEncrypted(format!(
+                "variable '{}' in {} is not encrypted, but the policy requires it",
+                var.key,
+                path.display()
+            )));
+        } else {
+            var.value
+        };
+
+        vars.insert(var.key, value);
+    }
+
+    Ok(vars)
+}
+
+/// Load `.env` from the current directory into the process environment.
+///
+/// Equivalent to `load_path(".env")`.
+///
+/// # Safety
+/// This calls [`std::env::set_var`]; see its documentation. Call it early in `main`,
+/// before any other threads are spawned.
+///
+/// # Examples
+/// ```rust,no_run
+/// // SAFETY: called at startup, before any threads are spawned.
+/// unsafe { sealed_env::load()? };
+///
+/// let password = std::env::var("DATABASE_PASSWORD")?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub unsafe fn load() -> Result<(), SealedEnvError> {
+    // SAFETY: forwarded to the caller.
+    unsafe { load_path(".env") }
+}
+
+/// Load a `.env` file into the process environment.
+///
+/// Encrypted values are decrypted with the environment keys as in [`from_path`]. Variables that
+/// are already set in the process environment are left untouched.
+///
+/// # Safety
+/// This calls [`std::env::set_var`]; see its documentation. Call it early in `main`,
+/// before any other threads are spawned.
+///
+/// # Examples
+/// ```rust,no_run
+/// // SAFETY: called at startup, before any threads are spawned.
+/// unsafe { sealed_env::load_path(".env.development")? };
+/// # Ok::<(), sealed_env::SealedEnvError>(())
+/// ```
+pub unsafe fn load_path(path: impl AsRef<Path>) -> Result<(), SealedEnvError> {
+    // SAFETY: forwarded to the caller.
+    unsafe { set_vars(from_path(path)?) };
+    Ok(())
+}
+
+/// Set every variable that is not already set in the process environment.
+///
+/// # Safety
+/// See [`std::env::set_var`].
+unsafe fn set_vars(vars: HashMap<String, String>) {
+    for (name, value) in vars {
+        if env::var_os(&name).is_none() {
+            // SAFETY: forwarded to the caller.
+            unsafe { env::set_var(name, value) };
+        }
+    }
+}
+
+fn sealed_or_env<'a>(
+    sealed: Option<&'a Sealed>,
+    from_env: &'a mut Option<Sealed>,
+) -> Result<&'a Sealed, SealedEnvError> {
+    match (sealed, from_env) {
+        (Some(sealed), _) => Ok(sealed),
+        (None, Some(sealed)) => Ok(sealed),
+        (None, from_env) => Ok(from_env.insert(Sealed::from_env()?)),
+    }
+}
+
+fn decrypt_env(name: &str, value: &str) -> Result<SecretString, SealedEnvError> {
+    Sealed::from_env()?.decrypt_secret(name, value)
+}
+
+fn expose(value: SecretString) -> String {
+    value.expose_secret().to_string()
+}
+
+fn var_with<F>(name: &str, decrypt: F) -> Result<SecretString, SealedEnvError>
+where
+    F: FnOnce(&str, &str) -> Result<SecretString, SealedEnvError>,
+{
+    let value = env::var(name).map_err(|_| {
+        SealedEnvError::MissingVar(format!("environment variable '{}' is not set", name))
+    })?;
+
+    if !is_encrypted(&value) {
+        return Err(SealedEnvError::NotEncrypted(format!(
+            "environment variable '{}' is not encrypted",
+            name
+        )));
+    }
+
+    decrypt(name, &value)
+}
+
+fn var_or_plain_with<F>(name: &str, decrypt: F) -> Result<SecretString, SealedEnvError>
+where
+    F: FnOnce(&str, &str) -> Result<SecretString, SealedEnvError>,
+{
+    let value = env::var(name).map_err(|_| {
+        SealedEnvError::MissingVar(format!("environment variable '{}' is not set", name))
+    })?;
+
+    if !is_encrypted(&value) {
+        return Ok(SecretString::from(value));
+    }
+
+    decrypt(name, &value)
+}
+
+fn var_optional_with<F>(name: &str, decrypt: F) -> Result<Option<SecretString>, SealedEnvError>
+where
+    F: FnOnce(&str, &str) -> Result<SecretString, SealedEnvError>,
+{
     let value = match env::var(name) {
         Ok(value) => value,
         Err(env::VarError::NotPresent) => return Ok(None),
@@ -141,92 +419,64 @@ pub fn var_optional(name: &str) -> Result<Option<String>, SealedEnvError> {
     };
 
     if !is_encrypted(&value) {
-        return Ok(Some(value));
+        return Ok(Some(SecretString::from(value)));
     }
 
-    let key_b64 = env::var("SEALED_KEY")
-        .map_err(|_| SealedEnvError::MissingKey("SEALED_KEY is not set".to_string()))?;
-
-    let key = decode_key(&SecretString::from(key_b64))?;
-    let decrypted = decrypt_value(&key, name, &value)?;
-
-    String::from_utf8(decrypted.expose_secret().to_vec())
-        .map_err(|_| SealedEnvError::Crypto("decrypted value is not valid UTF-8".to_string()))
-        .map(Some)
+    decrypt(name, &value).map(Some)
 }
 
-fn decode_key(b64: &SecretString) -> Result<SecretSlice<u8>, SealedEnvError> {
-    let decoded = general_purpose::STANDARD
-        .decode(b64.expose_secret())
-        .map_err(|_| SealedEnvError::Crypto("invalid base64 key".to_string()))?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sealed_core::mac::with_mac;
+    use std::path::PathBuf;
 
-    if decoded.len() != 32 {
-        return Err(SealedEnvError::Crypto(
-            "key must be 32 bytes after base64 decode".to_string(),
-        ));
+    const KEY: [u8; 32] = [7; 32];
+
+    fn env_file(name: &str, content: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("sealed-env-{}-{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        path
     }
 
-    Ok(SecretSlice::from(decoded))
-}
+    fn read(path: &Path, sealed: Sealed) -> Result<HashMap<String, String>, SealedEnvError> {
+        let vars = sealed.read_path(path);
+        fs::remove_file(path).unwrap();
+        vars
+    }
 
-fn decrypt_value(
-    key: &SecretSlice<u8>,
-    var_name: &str,
-    encrypted: &str,
-) -> Result<SecretSlice<u8>, SealedEnvError> {
-    let (nonce, ciphertext) = parse_encrypted(encrypted)?;
-    let key_bytes = key.expose_secret();
+    #[test]
+    fn require_mac_rejects_a_file_without_a_trailer() {
+        let sealed = Sealed::from_bytes(&KEY).unwrap().require_mac();
+        let path = env_file("missing-mac", "A=1\n");
 
-    if key_bytes.len() != 32 {
-        return Err(SealedEnvError::Crypto(
-            "key must be 32 bytes after base64 decode".to_string(),
+        assert!(matches!(
+            read(&path, sealed),
+            Err(SealedEnvError::Crypto(CryptoError::MissingMac))
         ));
     }
 
-    let cipher = ChaCha20Poly1305::new(Key::from_slice(key_bytes));
-    let plaintext = cipher
-        .decrypt(
-            Nonce::from_slice(&nonce),
-            Payload {
-                msg: &ciphertext,
-                aad: var_name.as_bytes(),
-            },
-        )
-        .map_err(|_| SealedEnvError::Crypto("decryption failed (bad key or data)".to_string()))?;
+    #[test]
+    fn require_mac_accepts_a_matching_trailer() {
+        let key = Key::from_bytes(&KEY).unwrap();
+        let sealed = Sealed::new(key).require_mac();
+        let path = env_file(
+            "valid-mac",
+            &with_mac("A=1\n", &Key::from_bytes(&KEY).unwrap()),
+        );
 
-    Ok(SecretSlice::from(plaintext))
-}
-
-fn parse_encrypted(value: &str) -> Result<(Vec<u8>, Vec<u8>), SealedEnvError> {
-    let mut parts = value.splitn(3, ':');
-
-    let tag = parts.next();
-    let nonce_b64 = parts.next();
-    let ct_b64 = parts.next();
-
-    if tag != Some("ENCv1") || nonce_b64.is_none() || ct_b64.is_none() {
-        return Err(SealedEnvError::Crypto(
-            "invalid encrypted value format".to_string(),
-        ));
+        assert_eq!(read(&path, sealed).unwrap()["A"], "1");
     }
 
-    let nonce = general_purpose::STANDARD
-        .decode(nonce_b64.unwrap())
-        .map_err(|_| SealedEnvError::Crypto("invalid base64 nonce".to_string()))?;
+    #[test]
+    fn rejects_a_file_that_does_not_match_its_trailer() {
+        let sealed = Sealed::from_bytes(&KEY).unwrap();
+        let content = with_mac("A=1\n", &Key::from_bytes(&KEY).unwrap()).replace("A=1", "A=2");
+        let path = env_file("tampered-mac", &content);
 
-    if nonce.len() != 12 {
-        return Err(SealedEnvError::Crypto(
-            "nonce must be 12 bytes after base64 decode".to_string(),
+        assert!(matches!(
+            read(&path, sealed),
+            Err(SealedEnvError::Crypto(CryptoError::MacMismatch))
         ));
     }
-
-    let ciphertext = general_purpose::STANDARD
-        .decode(ct_b64.unwrap())
-        .map_err(|_| SealedEnvError::Crypto("invalid base64 ciphertext".to_string()))?;
-
-    Ok((nonce, ciphertext))
-}
-
-fn is_encrypted(value: &str) -> bool {
-    value.starts_with("ENCv1:")
 }
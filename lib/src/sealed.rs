@@ -0,0 +1,348 @@
+use sealed_core::config::{CONFIG_FILE, Config};
+use sealed_core::mac::FileMac;
+use sealed_core::passphrase::{PassphraseKey, PassphraseParams};
+use sealed_core::policy::{Policy, Rule};
+use sealed_core::provider::{EnvKey, FileKey, KeyProvider};
+use sealed_core::{Error as CryptoError, Identity, Key, Keyring};
+use secrecy::{ExposeSecret, SecretString};
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+
+use crate::{
+    SealedEnvError, expose, from_path_with, var_optional_with, var_or_plain_with, var_with,
+};
+
+/// A decryptor holding an already-decoded key or identity.
+///
+/// `Sealed` exposes the same lookups as the free functions, but the key is supplied
+/// explicitly and decoded once, so it does not need to stay in the process environment.
+/// Values encrypted to recipients (`ENCr1:`) are decrypted with an X25519 [`Identity`].
+///
+/// # Examples
+/// ```rust,no_run
+/// use sealed_env::Sealed;
+///
+/// let sealed = Sealed::from_base64("<base64-key>")?;
+///
+/// let secret = sealed.var("DATABASE_PASSWORD")?;
+/// let maybe_plain = sealed.var_or_plain("FEATURE_FLAG")?;
+/// let optional = sealed.var_optional("OPTIONAL_SECRET")?;
+/// # Ok::<(), sealed_env::SealedEnvError>(())
+/// ```
+#[derive(Debug)]
+pub struct Sealed {
+    keyring: Keyring,
+    context: Option<String>,
+    policy: Option<Policy>,
+    deny_duplicates: bool,
+    require_mac: bool,
+}
+
+impl Sealed {
+    /// Create a decryptor from a decoded key.
+    pub fn new(key: Key) -> Self {
+        Sealed::from_keyring(Keyring::from(key))
+    }
+
+    /// Create a decryptor from a set of keys and identities.
+    pub fn from_keyring(keyring: Keyring) -> Self {
+        Sealed {
+            keyring,
+            context: None,
+            policy: None,
+            deny_duplicates: false,
+            require_mac: false,
+        }
+    }
+
+    /// Read values in `context` (an environment name such as `production`).
+    ///
+    /// Values bound to a different context, or to none, are rejected with
+    /// [`sealed_core::Error::ContextMismatch`], so a value copied from another environment's
+    /// file cannot be decrypted silently.
+    pub fn with_context(mut self, context: impl Into<String>) -> Self {
+        self.context = Some(context.into());
+        self
+    }
+
+    /// Enforce `policy` (strict mode): variables it requires to be encrypted are read as with
+    /// [`Sealed::var`] even through [`Sealed::var_or_plain`] and [`Sealed::var_optional`], and
+    /// variables it requires to stay plain fail with [`SealedEnvError::PolicyViolation`] if
+    /// they are encrypted.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// use sealed_env::{Config, Sealed};
+    ///
+    /// let policy = Config::load(".sealed.toml".as_ref())?.policy;
+    /// let sealed = Sealed::from_env()?.with_policy(policy);
+    ///
+    /// // Fails if STRIPE_SECRET matches `encrypted` in the policy but is plaintext.
+    /// let secret = sealed.var_or_plain("STRIPE_SECRET")?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Reject env files that assign a variable more than once: [`Sealed::read_path`] and
+    /// [`Sealed::load_path`] fail with [`SealedEnvError::DuplicateVar`] instead of letting the
+    /// last assignment win.
+    pub fn deny_duplicates(mut self) -> Self {
+        self.deny_duplicates = true;
+        self
+    }
+
+    /// Reject env files without a `# sealed-mac:` trailer: [`Sealed::read_path`] and
+    /// [`Sealed::load_path`] fail with [`sealed_core::Error::MissingMac`], so deleting the
+    /// trailer does not disable the whole-file check. Use it when the project config sets
+    /// `mac = true`.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// use sealed_env::{Config, Sealed};
+    ///
+    /// let config = Config::load(".sealed.toml".as_ref())?;
+    /// let mut sealed = Sealed::from_env()?;
+    /// if config.mac {
+    ///     sealed = sealed.require_mac();
+    /// }
+    /// let vars = sealed.read_path(".env")?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn require_mac(mut self) -> Self {
+        self.require_mac = true;
+        self
+    }
+
+    /// Create a decryptor from an X25519 identity.
+    pub fn from_identity(identity: Identity) -> Self {
+        Sealed::from_keyring(Keyring::from(identity))
+    }
+
+    /// Create a decryptor from an identity file written by `sealed keygen --recipient`.
+    pub fn from_identity_file(path: impl AsRef<Path>) -> Result<Self, SealedEnvError> {
+        Ok(Sealed::from_identity(Identity::from_file(path.as_ref())?))
+    }
+
+    /// Create a decryptor from 32 raw key bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SealedEnvError> {
+        Ok(Sealed::new(Key::from_bytes(bytes)?))
+    }
+
+    /// Create a decryptor from a base64-encoded key.
+    pub fn from_base64(b64: &str) -> Result<Self, SealedEnvError> {
+        Ok(Sealed::new(Key::from_base64(b64)?))
+    }
+
+    /// Create a decryptor from a file containing a base64-encoded key.
+    ///
+    /// Trailing newlines are ignored, so files written by `sealed keygen -o` work as-is.
+    pub fn from_key_file(path: impl AsRef<Path>) -> Result<Self, SealedEnvError> {
+        Sealed::from_provider(FileKey::new(path.as_ref()))
+    }
+
+    /// Create a decryptor from a keyring file: base64 keys separated by commas, spaces or
+    /// newlines, with `#` comment lines. Each value is decrypted with the key whose id it
+    /// records, or by trying each key for `ENCv1` values.
+    pub fn from_keyring_file(path: impl AsRef<Path>) -> Result<Self, SealedEnvError> {
+        match Keyring::from_file(path.as_ref()) {
+            Ok(keyring) => Ok(Sealed::from_keyring(keyring)),
+            Err(CryptoError::KeyUnavailable(msg)) => Err(SealedEnvError::MissingKey(msg)),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Create a decryptor from the process environment: `SEALED_KEY`, `SEALED_KEYS` (several
+    /// keys, separated by commas or whitespace), `SEALED_PASSPHRASE` and `SEALED_IDENTITY`. At
+    /// least one of them must be set; all that are set are used. `SEALED_CONTEXT`, if set,
+    /// selects the context values are read in; see [`Sealed::with_context`].
+    ///
+    /// A key is derived from `SEALED_PASSPHRASE` with the `[passphrase]` table of
+    /// `.sealed.toml` in the current directory, as the `sealed` CLI does. The derivation is
+    /// deliberately slow, so build one `Sealed` rather than calling the free functions
+    /// repeatedly.
+    pub fn from_env() -> Result<Self, SealedEnvError> {
+        let keys = env::var("SEALED_KEYS").ok().filter(|s| !s.is_empty());
+        let passphrase = env::var("SEALED_PASSPHRASE").ok().filter(|s| !s.is_empty());
+        let identity = env::var("SEALED_IDENTITY").ok().filter(|s| !s.is_empty());
+        let mut keyring = Keyring::new();
+
+        match EnvKey::default().provide() {
+            Ok(key) => keyring.add_key(key),
+            Err(CryptoError::KeyUnavailable(_))
+                if keys.is_some() || passphrase.is_some() || identity.is_some() => {}
+            Err(CryptoError::KeyUnavailable(msg)) => {
+                return Err(SealedEnvError::MissingKey(format!(
+                    "{} (or set SEALED_KEYS, SEALED_PASSPHRASE or SEALED_IDENTITY)",
+                    msg
+                )));
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        if let Some(keys) = keys {
+            keyring.extend(SecretString::from(keys).expose_secret().parse()?);
+        }
+
+        if let Some(passphrase) = passphrase {
+            let params = Config::load_or_default(Path::new(CONFIG_FILE))?
+                .passphrase
+                .ok_or_else(|| {
+                    SealedEnvError::MissingKey(format!(
+                        "SEALED_PASSPHRASE requires a [passphrase] table in {}",
+                        CONFIG_FILE
+                    ))
+                })?;
+            keyring.add_key(PassphraseKey::new(SecretString::from(passphrase), params).provide()?);
+        }
+
+        if let Some(identity) = identity {
+            keyring.add_identity(identity.parse()?);
+        }
+
+        let sealed = Sealed::from_keyring(keyring);
+        match env::var("SEALED_CONTEXT") {
+            Ok(context) if !context.is_empty() => Ok(sealed.with_context(context)),
+            _ => Ok(sealed),
+        }
+    }
+
+    /// Create a decryptor from a passphrase, deriving the key with Argon2id.
+    ///
+    /// `params` is the `[passphrase]` table of the project's `.sealed.toml`.
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// use sealed_env::{Config, Sealed, SecretString};
+    ///
+    /// let config = Config::load(".sealed.toml".as_ref())?;
+    /// let params = config.passphrase.expect("no [passphrase] table");
+    /// let passphrase = SecretString::from(std::env::var("SEALED_PASSPHRASE")?);
+    ///
+    /// let sealed = Sealed::from_passphrase(passphrase, params)?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn from_passphrase(
+        passphrase: SecretString,
+        params: PassphraseParams,
+    ) -> Result<Self, SealedEnvError> {
+        Sealed::from_provider(PassphraseKey::new(passphrase, params))
+    }
+
+    /// Create a decryptor from any [`KeyProvider`].
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// use sealed_env::Sealed;
+    /// use sealed_env::provider::CommandKey;
+    ///
+    /// let sealed = Sealed::from_provider(CommandKey::new("pass show project/sealed"))?;
+    /// # Ok::<(), sealed_env::SealedEnvError>(())
+    /// ```
+    pub fn from_provider(provider: impl KeyProvider) -> Result<Self, SealedEnvError> {
+        match provider.provide() {
+            Ok(key) => Ok(Sealed::new(key)),
+            Err(CryptoError::KeyUnavailable(msg)) => Err(SealedEnvError::MissingKey(msg)),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Read an encrypted variable; see [`crate::var`].
+    pub fn var(&self, name: &str) -> Result<String, SealedEnvError> {
+        self.var_secret(name).map(expose)
+    }
+
+    /// Read an encrypted variable as a [`SecretString`]; see [`crate::var_secret`].
+    pub fn var_secret(&self, name: &str) -> Result<SecretString, SealedEnvError> {
+        var_with(name, |name, value| self.decrypt_secret(name, value))
+    }
+
+    /// Read a variable, passing plaintext through; see [`crate::var_or_plain`].
+    pub fn var_or_plain(&self, name: &str) -> Result<String, SealedEnvError> {
+        self.var_or_plain_secret(name).map(expose)
+    }
+
+    /// Like [`Sealed::var_or_plain`], but returns a [`SecretString`].
+    pub fn var_or_plain_secret(&self, name: &str) -> Result<SecretString, SealedEnvError> {
+        if self.rule(name) == Some(Rule::Encrypted) {
+            return self.var_secret(name);
+        }
+
+        var_or_plain_with(name, |name, value| self.decrypt_secret(name, value))
+    }
+
+    /// Read a variable, returning `Ok(None)` if it is not set; see [`crate::var_optional`].
+    pub fn var_optional(&self, name: &str) -> Result<Option<String>, SealedEnvError> {
+        self.var_optional_secret(name)
+            .map(|value| value.map(expose))
+    }
+
+    /// Like [`Sealed::var_optional`], but returns a [`SecretString`].
+    pub fn var_optional_secret(&self, name: &str) -> Result<Option<SecretString>, SealedEnvError> {
+        if self.rule(name) == Some(Rule::Encrypted) && env::var_os(name).is_some() {
+            return self.var_secret(name).map(Some);
+        }
+
+        var_optional_with(name, |name, value| self.decrypt_secret(name, value))
+    }
+
+    /// Decrypt a sealed value (`ENCv3:`, `ENCv2:`, `ENCv1:`, `ENCr2:` or `ENCr1:`) that was
+    /// stored under `name`.
+    pub fn decrypt(&self, name: &str, value: &str) -> Result<String, SealedEnvError> {
+        self.decrypt_secret(name, value).map(expose)
+    }
+
+    /// Like [`Sealed::decrypt`], but returns a [`SecretString`].
+    pub fn decrypt_secret(&self, name: &str, value: &str) -> Result<SecretString, SealedEnvError> {
+        if self.rule(name) == Some(Rule::Plain) {
+            return Err(SealedEnvError::PolicyViolation(format!(
+                "'{}' is encrypted, but the policy requires it to stay plain",
+                name
+            )));
+        }
+
+        Ok(self.keyring.decrypt(self.context.as_deref(), name, value)?)
+    }
+
+    /// Parse a `.env` file and return its decrypted variables; see [`crate::from_path`].
+    ///
+    /// With a policy, every variable it requires to be encrypted must be encrypted in the file.
+    /// With [`Sealed::deny_duplicates`], no variable may be assigned more than once. With
+    /// [`Sealed::require_mac`], the file must have a `# sealed-mac:` trailer.
+    pub fn read_path(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<HashMap<String, String>, SealedEnvError> {
+        from_path_with(path.as_ref(), Some(self))
+    }
+
+    /// Load a `.env` file into the process environment; see [`crate::load_path`].
+    ///
+    /// # Safety
+    /// This calls [`std::env::set_var`]; see its documentation. Call it early in `main`,
+    /// before any other threads are spawned.
+    pub unsafe fn load_path(&self, path: impl AsRef<Path>) -> Result<(), SealedEnvError> {
+        // SAFETY: forwarded to the caller.
+        unsafe { crate::set_vars(self.read_path(path)?) };
+        Ok(())
+    }
+
+    pub(crate) fn denies_duplicates(&self) -> bool {
+        self.deny_duplicates
+    }
+
+    pub(crate) fn requires_mac(&self) -> bool {
+        self.require_mac
+    }
+
+    pub(crate) fn rule(&self, name: &str) -> Option<Rule> {
+        self.policy.as_ref().and_then(|policy| policy.rule(name))
+    }
+
+    pub(crate) fn verify_mac(&self, mac: &FileMac, content: &str) -> Result<(), SealedEnvError> {
+        Ok(mac.verify(&self.keyring, content)?)
+    }
+}